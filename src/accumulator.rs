@@ -0,0 +1,307 @@
+use crate::client_verify::*;
+use crate::mpt::*;
+use crate::node_generic::*;
+use crate::structs::*;
+use crate::util::*;
+use serde::{Deserialize, Serialize};
+use talk::crypto::primitives::hash::Hash;
+
+/**
+ * Append-only binary Merkle accumulator over the roots of sealed batches, shaped as the
+ * trees of Certificate Transparency (RFC 6962): the left subtree of every node holds the
+ * largest power of two of its entries. The entry N is the Leaf whose key is N and whose
+ * value is the root of the batch N, and the Internal nodes are hashed as the ones of a
+ * MerkleTree, so that an inclusion proof is a plain Proof. The position of an entry depends
+ * on the size of the accumulator rather than on the path of its key: an inclusion proof is
+ * verified by get_entry_root_hash, which checks the position for the given size.
+ * A ConsistencyProof shows that the accumulator of a given size is a prefix of a larger one.
+**/
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct MerkleAccumulator {
+    roots: Vec<Hash>,
+    // levels[h][i] is the Hash of the perfect subtree holding the entries i * 2^h .. (i + 1) * 2^h
+    levels: Vec<Vec<Hash>>,
+}
+
+/// A proof that the accumulator of the old size is a prefix of the one of the new size.
+/// The Hashes are the ones of RFC 6962, from the deepest subtree to the topmost one.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ConsistencyProof {
+    old_size: u64,
+    new_size: u64,
+    hashes: Vec<Hash>,
+}
+
+impl MerkleAccumulator {
+    /// Returns a new, empty MerkleAccumulator.
+    pub fn new() -> Self {
+        MerkleAccumulator {
+            roots: Vec::new(),
+            levels: Vec::new(),
+        }
+    }
+
+    /// Returns the sequence number of the given root, appended as the last entry.
+    pub fn append(&mut self, root_hash: Hash) -> u64 {
+        let sequence = self.roots.len() as u64;
+        self.roots.push(root_hash);
+
+        let mut this_hash = MerkleAccumulator::create_entry_hash(sequence, root_hash);
+        let mut height = 0;
+        loop {
+            if self.levels.len() == height {
+                self.levels.push(Vec::new());
+            }
+            self.levels[height].push(this_hash);
+            let level = &self.levels[height];
+            // a left subtree waits for its right sibling before its parent is hashed
+            if level.len() % 2 == 1 {
+                return sequence;
+            }
+            this_hash = Internal::<(), ()>::create_hash(level[level.len() - 2], level[level.len() - 1]);
+            height += 1;
+        }
+    }
+
+    /// Returns the sequence number of the given MerkleTree, sealed as the next entry.
+    /// Computes the hashes of the MerkleTree and appends its root.
+    pub fn append_sealed<K, V>(&mut self, mpt: &mut MerkleTree<K, V>) -> u64
+    where
+        K: Serialize + KeyPath + Clone + Eq,
+        V: Serialize + Clone,
+    {
+        let root_hash = mpt.compute_hashes();
+        self.append(root_hash)
+    }
+
+    /// Returns the root appended with the given sequence number, if any.
+    pub fn get_root(&self, sequence: u64) -> Option<&Hash> {
+        self.roots.get(sequence as usize)
+    }
+
+    /// Returns the number of entries of the MerkleAccumulator.
+    pub fn len(&self) -> u64 {
+        self.roots.len() as u64
+    }
+
+    /// Returns true if the MerkleAccumulator holds no entry.
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// Returns the Hash of the MerkleAccumulator.
+    pub fn get_hash(&self) -> Hash {
+        self.get_hash_at(self.len())
+    }
+
+    /// Returns the Hash the MerkleAccumulator had when it held the given number of entries.
+    /// Panics if the given size exceeds the current one.
+    pub fn get_hash_at(&self, size: u64) -> Hash {
+        assert!(size <= self.len(), "size larger than the MerkleAccumulator");
+        if size == 0 {
+            return Empty::get_hash();
+        }
+        self.range_hash(0, size)
+    }
+
+    /// Returns a Proof that the given sequence number was appended with its root, against
+    /// the Hash of the MerkleAccumulator when it held the given number of entries, or None
+    /// if the sequence number does not fall within the given size.
+    /// Panics if the given size exceeds the current one.
+    pub fn prove_at(&self, sequence: u64, size: u64) -> Option<Proof> {
+        assert!(size <= self.len(), "size larger than the MerkleAccumulator");
+        if sequence >= size {
+            return None;
+        }
+        let siblings = get_entry_path(sequence, size)
+            .into_iter()
+            .rev()
+            .map(|(start, count, direction)| {
+                Sibling::new(self.range_hash(start, count), direction)
+            })
+            .collect();
+        Some(Proof::new(siblings))
+    }
+
+    /// Returns a Proof that the given sequence number was appended with its root, against
+    /// the current Hash of the MerkleAccumulator, or None if the sequence number is unknown.
+    pub fn prove(&self, sequence: u64) -> Option<Proof> {
+        self.prove_at(sequence, self.len())
+    }
+
+    /// Returns a ConsistencyProof from the given old size to the current size.
+    /// Panics if the given size exceeds the current one.
+    pub fn prove_consistency(&self, old_size: u64) -> ConsistencyProof {
+        assert!(old_size <= self.len(), "size larger than the MerkleAccumulator");
+        let mut hashes = Vec::<Hash>::new();
+        if old_size > 0 {
+            self.get_subproof(old_size, 0, self.len(), true, &mut hashes);
+        }
+        ConsistencyProof::new(old_size, self.len(), hashes)
+    }
+
+    /// Returns the Hash of the entry with the given sequence number and root, that is the
+    /// Hash of a Leaf whose key is the sequence number and whose value is the root.
+    pub fn create_entry_hash(sequence: u64, root_hash: Hash) -> Hash {
+        Leaf::<u64, Hash>::create_leaf_hash(sequence, root_hash)
+    }
+
+    /// Returns the Hash of the subtree holding the given number of entries from the given
+    /// start, which is a multiple of the largest power of two not exceeding the number.
+    fn range_hash(&self, start: u64, count: u64) -> Hash {
+        if count.is_power_of_two() {
+            let height = count.trailing_zeros() as usize;
+            return self.levels[height][(start >> height) as usize];
+        }
+        let split = largest_power_of_two_below(count);
+        Internal::<(), ()>::create_hash(
+            self.range_hash(start, split),
+            self.range_hash(start + split, count - split),
+        )
+    }
+
+    /// Recursively appends to the given vector the Hashes proving that the first old_size
+    /// entries of the subtree of the given start and count are consistent with the subtree.
+    /// The Hash of the old subtree itself is omitted if it is known to the verifier.
+    fn get_subproof(&self, old_size: u64, start: u64, count: u64, known: bool, hashes: &mut Vec<Hash>) {
+        if old_size == count {
+            if !known {
+                hashes.push(self.range_hash(start, count));
+            }
+            return;
+        }
+        let split = largest_power_of_two_below(count);
+        if old_size <= split {
+            self.get_subproof(old_size, start, split, known, hashes);
+            hashes.push(self.range_hash(start + split, count - split));
+        } else {
+            self.get_subproof(old_size - split, start + split, count - split, false, hashes);
+            hashes.push(self.range_hash(start, split));
+        }
+    }
+}
+
+impl ConsistencyProof {
+    /// Returns a new ConsistencyProof.
+    pub fn new(old_size: u64, new_size: u64, hashes: Vec<Hash>) -> Self {
+        ConsistencyProof {
+            old_size,
+            new_size,
+            hashes,
+        }
+    }
+
+    /// Returns the number of entries of the old MerkleAccumulator.
+    pub fn get_old_size(&self) -> u64 {
+        self.old_size
+    }
+
+    /// Returns the number of entries of the new MerkleAccumulator.
+    pub fn get_new_size(&self) -> u64 {
+        self.new_size
+    }
+
+    /// Returns a reference to the Hashes of the ConsistencyProof.
+    pub fn get_hashes(&self) -> &Vec<Hash> {
+        &self.hashes
+    }
+
+    /// Returns true if the ConsistencyProof shows that the MerkleAccumulator whose Hash is
+    /// the given old one is a prefix of the MerkleAccumulator whose Hash is the given new one.
+    pub fn verify(&self, old_hash: Hash, new_hash: Hash) -> bool {
+        let (old_size, new_size) = (self.old_size, self.new_size);
+        if old_size > new_size {
+            return false;
+        }
+        if old_size == new_size {
+            return self.hashes.is_empty() && old_hash == new_hash;
+        }
+        if old_size == 0 {
+            return self.hashes.is_empty() && old_hash == Empty::get_hash();
+        }
+
+        let mut hashes = self.hashes.iter();
+        // the old MerkleAccumulator is a perfect subtree of the new one, whose Hash is known
+        let first = if old_size.is_power_of_two() {
+            old_hash
+        } else {
+            match hashes.next() {
+                Some(h) => *h,
+                None => return false,
+            }
+        };
+
+        let (mut old_index, mut new_index) = (old_size - 1, new_size - 1);
+        while old_index & 1 == 1 {
+            old_index >>= 1;
+            new_index >>= 1;
+        }
+        let (mut old_root, mut new_root) = (first, first);
+        for h in hashes {
+            if new_index == 0 {
+                return false;
+            }
+            if old_index & 1 == 1 || old_index == new_index {
+                old_root = Internal::<(), ()>::create_hash(*h, old_root);
+                new_root = Internal::<(), ()>::create_hash(*h, new_root);
+                while old_index & 1 == 0 && old_index != 0 {
+                    old_index >>= 1;
+                    new_index >>= 1;
+                }
+            } else {
+                new_root = Internal::<(), ()>::create_hash(new_root, *h);
+            }
+            old_index >>= 1;
+            new_index >>= 1;
+        }
+        old_root == old_hash && new_root == new_hash && new_index == 0
+    }
+}
+
+/// Returns a Result which contains: the Hash of the MerkleAccumulator of the given size,
+/// computed according to the given Proof of the entry with the given sequence number and
+/// root; Err(()) if the Siblings of the Proof are not placed as the ones of that entry in a
+/// MerkleAccumulator of that size.
+pub fn get_entry_root_hash(proof: Proof, sequence: u64, size: u64, root_hash: Hash) -> Result<Hash, ()> {
+    if sequence >= size {
+        return Err(());
+    }
+    let path = get_entry_path(sequence, size);
+    let placed = path.len() == proof.get_siblings().len()
+        && path
+            .iter()
+            .rev()
+            .zip(proof.get_siblings().iter())
+            .all(|((_, _, direction), sibling)| direction == sibling.get_direction());
+    if !placed {
+        return Err(());
+    }
+    let entry_hash = MerkleAccumulator::create_entry_hash(sequence, root_hash);
+    Ok(get_root_hash_from_leaf_hash(proof, entry_hash))
+}
+
+/// Returns, from the root down to the entry with the given sequence number in a
+/// MerkleAccumulator of the given size, the start and the count of the subtree left aside
+/// at every level, together with the Direction of the Sibling it makes.
+fn get_entry_path(sequence: u64, size: u64) -> Vec<(u64, u64, Direction)> {
+    let mut path = Vec::<(u64, u64, Direction)>::new();
+    let (mut start, mut count) = (0, size);
+    while count > 1 {
+        let split = largest_power_of_two_below(count);
+        if sequence < start + split {
+            path.push((start + split, count - split, Right {}.into()));
+            count = split;
+        } else {
+            path.push((start, split, Left {}.into()));
+            start += split;
+            count -= split;
+        }
+    }
+    path
+}
+
+/// Returns the largest power of two strictly smaller than the given number, greater than one.
+fn largest_power_of_two_below(count: u64) -> u64 {
+    1 << (63 - (count - 1).leading_zeros())
+}
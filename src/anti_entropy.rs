@@ -0,0 +1,234 @@
+use crate::mpt::*;
+use crate::node_generic::*;
+use crate::structs::*;
+use crate::util::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use talk::crypto::primitives::hash::Hash;
+
+/**
+ * Anti-entropy protocol bringing a replica of a MerkleTree up to date with a source one.
+ * The replica descends from the root, asking the source for the node found at a given
+ * prefix of bits (true <--> Right, false <--> Left), and only descends into the children
+ * whose Hashes differ from its own. Once the source answers with Leaves, the replica
+ * replaces its own Leaves under that prefix with them. Every SyncResponse must answer an
+ * outstanding SyncRequest and match the Hash the source announced for that prefix, so that
+ * a faulty source cannot write outside of what was asked for. The protocol is transport-
+ * agnostic: the caller moves SyncRequests and SyncResponses around, serialized as it likes.
+**/
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SyncRequest {
+    prefix: Vec<bool>,
+}
+
+impl SyncRequest {
+    /// Returns a new SyncRequest.
+    pub fn new(p: Vec<bool>) -> SyncRequest {
+        SyncRequest { prefix: p }
+    }
+
+    /// Returns a reference to the prefix asked for by the SyncRequest invoking the method.
+    pub fn get_prefix(&self) -> &Vec<bool> {
+        &self.prefix
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SyncResponse<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    /// The source holds an Internal node at the prefix, whose children have the given Hashes.
+    Internal {
+        prefix: Vec<bool>,
+        left: Hash,
+        right: Hash,
+    },
+    /// The source holds the given Leaves, at most one, under the prefix.
+    Leaves {
+        prefix: Vec<bool>,
+        leaves: Vec<Leaf<K, V>>,
+    },
+}
+
+/// Returns a Result which contains: the SyncResponse of the given source MerkleTree to the
+/// given SyncRequest; Err(StaleHashes) if the Hash of an Internal node needed by the
+/// SyncResponse has been discarded by a modification since the last call to compute_hashes.
+pub fn respond<K, V>(source: &MerkleTree<K, V>, request: &SyncRequest) -> Result<SyncResponse<K, V>, StaleHashes>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    let prefix = request.get_prefix();
+    let mut node = source.get_root();
+    let mut index = 0;
+
+    while let NodeGeneric::Internal(n) = node {
+        if index == prefix.len() {
+            return Ok(SyncResponse::Internal {
+                prefix: prefix.clone(),
+                left: n.get_left().get_current_hash().ok_or(StaleHashes)?,
+                right: n.get_right().get_current_hash().ok_or(StaleHashes)?,
+            });
+        }
+        node = if prefix[index] {
+            n.get_right()
+        } else {
+            n.get_left()
+        };
+        index += 1;
+    }
+
+    let mut leaves = Vec::<Leaf<K, V>>::new();
+    if let NodeGeneric::Leaf(n) = node {
+        if starts_with_prefix(&n.get_key().key_path(), prefix) {
+            leaves.push(n.clone());
+        }
+    }
+    Ok(SyncResponse::Leaves {
+        prefix: prefix.clone(),
+        leaves,
+    })
+}
+
+/// Returns the Hash of the subtree holding every Leaf of the given MerkleTree whose key hash
+/// starts with the given prefix. Computes the hashes of the subtree if needed.
+fn range_hash<K, V>(mpt: &mut MerkleTree<K, V>, prefix: &[bool]) -> Hash
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    let mut node = mpt.get_mut_root();
+    let mut index = 0;
+
+    while index < prefix.len() {
+        match node {
+            NodeGeneric::Internal(n) => {
+                node = if prefix[index] {
+                    n.get_mut_right()
+                } else {
+                    n.get_mut_left()
+                };
+                index += 1;
+            }
+            // a Leaf less deep than the prefix is the whole subtree, if it belongs under it
+            NodeGeneric::Leaf(n) => {
+                return if starts_with_prefix(&n.get_key().key_path(), prefix) {
+                    n.get_hash()
+                } else {
+                    Empty::get_hash()
+                }
+            }
+            NodeGeneric::Empty(_) => return Empty::get_hash(),
+        }
+    }
+    node.update_hashes()
+}
+
+/// State of a replica synchronizing its MerkleTree with a source one. Every prefix to ask
+/// for is kept together with the Hash the source announced for it, if any.
+pub struct SyncSession {
+    pending: VecDeque<(Vec<bool>, Option<Hash>)>,
+    outstanding: HashMap<Vec<bool>, Option<Hash>>,
+    target_hash: Option<Hash>,
+}
+
+impl SyncSession {
+    /// Returns a new SyncSession, starting from the root.
+    pub fn new() -> SyncSession {
+        let mut pending = VecDeque::<(Vec<bool>, Option<Hash>)>::new();
+        pending.push_back((Vec::new(), None));
+        SyncSession {
+            pending,
+            outstanding: HashMap::new(),
+            target_hash: None,
+        }
+    }
+
+    /// Returns the next SyncRequest to send to the source, or None if no request is pending.
+    pub fn next_request(&mut self) -> Option<SyncRequest> {
+        let (prefix, expected_hash) = self.pending.pop_front()?;
+        self.outstanding.insert(prefix.clone(), expected_hash);
+        Some(SyncRequest::new(prefix))
+    }
+
+    /// Returns true once every SyncRequest has been answered and nothing is left to ask for.
+    pub fn is_done(&self) -> bool {
+        self.pending.is_empty() && self.outstanding.is_empty()
+    }
+
+    /// Returns the Hash of the root of the source MerkleTree, known once the SyncResponse
+    /// for the root has been handled. The replica has converged when its root has this Hash.
+    pub fn get_target_hash(&self) -> Option<Hash> {
+        self.target_hash
+    }
+
+    /// Returns Ok(()) once the given SyncResponse has been applied to the given replica:
+    /// either the children whose Hashes differ are scheduled to be asked for, or the Leaves
+    /// under the prefix are replaced. Returns Err(()), leaving the replica untouched, if the
+    /// prefix of the SyncResponse was not asked for, if its Hash differs from the one the
+    /// source announced, or if it carries Leaves which do not belong under its prefix.
+    /// A prefix whose SyncResponse is rejected for its Hash is asked for again.
+    pub fn handle_response<K, V>(
+        &mut self,
+        replica: &mut MerkleTree<K, V>,
+        response: SyncResponse<K, V>,
+    ) -> Result<(), ()>
+    where
+        K: Serialize + KeyPath + Clone + Eq,
+        V: Serialize + Clone,
+    {
+        let prefix = match &response {
+            SyncResponse::Internal { prefix, .. } | SyncResponse::Leaves { prefix, .. } => prefix.clone(),
+        };
+        let expected_hash = self.outstanding.remove(&prefix).ok_or(())?;
+
+        match response {
+            SyncResponse::Internal {
+                prefix,
+                left,
+                right,
+            } => {
+                let this_hash = Internal::<K, V>::create_hash(left, right);
+                if expected_hash.is_some_and(|h| h != this_hash) {
+                    self.pending.push_back((prefix, expected_hash));
+                    return Err(());
+                }
+                if prefix.is_empty() {
+                    self.target_hash = Some(this_hash);
+                }
+                for (bit, child_hash) in [(false, left), (true, right)].iter() {
+                    let mut child = prefix.clone();
+                    child.push(*bit);
+                    if range_hash(replica, &child) != *child_hash {
+                        self.pending.push_back((child, Some(*child_hash)));
+                    }
+                }
+                Ok(())
+            }
+            SyncResponse::Leaves { prefix, mut leaves } => {
+                if leaves.len() > 1 {
+                    return Err(());
+                }
+                // the Hash of every Leaf is recomputed rather than trusted
+                for leaf in leaves.iter_mut() {
+                    leaf.refresh_hash();
+                }
+                let this_hash = leaves.first().map_or(Empty::get_hash(), |leaf| leaf.get_hash());
+                if expected_hash.is_some_and(|h| h != this_hash) {
+                    self.pending.push_back((prefix, expected_hash));
+                    return Err(());
+                }
+                replica.replace_range(&prefix, leaves)
+            }
+        }
+    }
+}
+
+impl Default for SyncSession {
+    fn default() -> Self {
+        SyncSession::new()
+    }
+}
@@ -0,0 +1,926 @@
+use crate::entry::*;
+use crate::mpt::*;
+use crate::node_generic::*;
+use crate::structs::*;
+use crate::util::*;
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use talk::crypto::primitives::hash::Hash;
+
+/*
+ * Arena-backed representation of the Merkle Patricia Tree. Every node lives in a single
+ * vector and refers to its children through NodeIds, and an Empty child is the sentinel
+ * NodeId::EMPTY rather than an allocated node. The slots of removed nodes are reused by
+ * later insertions. The shape, hence the Hashes and the Proofs, is the one of a MerkleTree
+ * holding the same entries, so that both can be used interchangeably.
+*/
+
+/// The handle of a node in the arena of an ArenaMerkleTree.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub struct NodeId(u32);
+
+/// A Leaf borrowed mutably together with the cached Hashes of the Internal nodes above it.
+type LeafPathMut<'a, K, V> = (&'a mut Leaf<K, V>, Vec<&'a mut Option<Hash>>);
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub enum ArenaNode<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    Internal(ArenaInternal),
+    Leaf(Leaf<K, V>),
+    /// A free slot, to be reused by the next allocation.
+    Vacant,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct ArenaInternal {
+    left: NodeId,
+    right: NodeId,
+    my_hash: Option<Hash>,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct ArenaMerkleTree<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    nodes: Vec<ArenaNode<K, V>>,
+    free: Vec<NodeId>,
+    root: NodeId,
+    len: usize,
+}
+
+impl NodeId {
+    /// The handle of every Empty node, which is never allocated.
+    pub const EMPTY: NodeId = NodeId(u32::MAX);
+
+    /// Returns true if the NodeId is the one of an Empty node.
+    pub fn is_empty(&self) -> bool {
+        *self == NodeId::EMPTY
+    }
+
+    fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl ArenaInternal {
+    /// Returns a new ArenaInternal node.
+    pub fn new(left: NodeId, right: NodeId) -> Self {
+        ArenaInternal {
+            left,
+            right,
+            my_hash: None,
+        }
+    }
+
+    /// Returns the handle of the left child.
+    pub fn get_left(&self) -> NodeId {
+        self.left
+    }
+
+    /// Returns the handle of the right child.
+    pub fn get_right(&self) -> NodeId {
+        self.right
+    }
+
+    /// Returns the handle of the child in the given direction (true <--> Right, false <--> Left).
+    fn get_child(&self, direction: bool) -> NodeId {
+        if direction {
+            self.right
+        } else {
+            self.left
+        }
+    }
+
+    fn set_child(&mut self, direction: bool, child: NodeId) {
+        if direction {
+            self.right = child;
+        } else {
+            self.left = child;
+        }
+    }
+}
+
+impl<K, V> ArenaMerkleTree<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    /// Returns a new ArenaMerkleTree
+    pub fn new() -> ArenaMerkleTree<K, V> {
+        ArenaMerkleTree {
+            nodes: vec![ArenaNode::Internal(ArenaInternal::new(NodeId::EMPTY, NodeId::EMPTY))],
+            free: Vec::new(),
+            root: NodeId(0),
+            len: 0,
+        }
+    }
+
+    /// Returns a new ArenaMerkleTree holding the same entries as the given MerkleTree.
+    pub fn from_merkle_tree(mpt: &MerkleTree<K, V>) -> ArenaMerkleTree<K, V> {
+        let mut leaves = Vec::<Leaf<K, V>>::new();
+        mpt.get_root().get_leaves(&mut leaves);
+        let mut arena = ArenaMerkleTree::new();
+        for leaf in leaves {
+            arena.insert_leaf(leaf);
+        }
+        arena
+    }
+
+    /// Returns a new MerkleTree holding the same entries as the ArenaMerkleTree.
+    pub fn to_merkle_tree(&self) -> MerkleTree<K, V> {
+        let leaves = self
+            .nodes
+            .iter()
+            .filter_map(|node| match node {
+                ArenaNode::Leaf(n) => Some(n.clone()),
+                _ => None,
+            })
+            .collect();
+        MerkleTree::from_root(NodeGeneric::from_leaves(leaves, 0))
+    }
+
+    /// Returns the handle of the root of the ArenaMerkleTree, which is always an Internal node.
+    pub fn get_root(&self) -> NodeId {
+        self.root
+    }
+
+    /// Returns a reference to the node of the given handle, or None for an Empty node.
+    pub fn get_arena_node(&self, id: NodeId) -> Option<&ArenaNode<K, V>> {
+        if id.is_empty() {
+            None
+        } else {
+            Some(&self.nodes[id.index()])
+        }
+    }
+
+    /// Returns the number of nodes currently allocated in the arena.
+    pub fn get_node_count(&self) -> usize {
+        self.nodes.len() - self.free.len()
+    }
+
+    /// Returns the value previously associated to the given key, or None if the key was
+    /// not contained. Inserts a new Leaf in the ArenaMerkleTree if the key is not
+    /// contained or substitutes the current value associated to the given key.
+    /// Panics if there is a collision
+    pub fn insert(&mut self, key_to_add: K, value_to_add: V) -> Option<V> {
+        self.insert_leaf(Leaf::new(key_to_add, value_to_add))
+            .map(|old| old.get_value().clone())
+    }
+
+    /// Returns the value previously associated to the given key, or None if the key was
+    /// not contained. Inserts a new Leaf salted with the given salt in the ArenaMerkleTree if
+    /// the key is not contained or substitutes the current Leaf associated to the given key.
+    /// Panics if there is a collision
+    pub fn insert_salted(&mut self, key_to_add: K, value_to_add: V, salt: Hash) -> Option<V> {
+        self.insert_leaf(Leaf::new_salted(key_to_add, value_to_add, salt))
+            .map(|old| old.get_value().clone())
+    }
+
+    /// Returns the Leaf previously associated to the same key, or None if the key was
+    /// not contained. Inserts the given Leaf in the ArenaMerkleTree if its key is not contained
+    /// or substitutes the Leaf associated to the same key, Hash included.
+    /// Panics if there is a collision
+    pub fn insert_leaf(&mut self, leaf_to_add: Leaf<K, V>) -> Option<Leaf<K, V>> {
+        match self.try_insert_leaf(leaf_to_add) {
+            Ok(old) => old,
+            Err(Collision) => {
+                panic!("followed the same path: different keys but same hash ---> Collision")
+            }
+        }
+    }
+
+    /// Returns a Result which contains: the value previously associated to the given key, or
+    /// None if the key was not contained; Err(Collision), leaving the ArenaMerkleTree untouched,
+    /// if the given key collides with another key. Behaves as insert otherwise.
+    pub fn try_insert(&mut self, key_to_add: K, value_to_add: V) -> Result<Option<V>, Collision> {
+        self.try_insert_leaf(Leaf::new(key_to_add, value_to_add))
+            .map(|old| old.map(|old| old.get_value().clone()))
+    }
+
+    /// Returns a Result which contains: the Leaf previously associated to the same key, or
+    /// None if the key was not contained; Err(Collision), leaving the ArenaMerkleTree untouched,
+    /// if the key of the given Leaf collides with another key. Behaves as insert_leaf otherwise.
+    /// The cached Hashes along the path are discarded.
+    pub fn try_insert_leaf(&mut self, leaf_to_add: Leaf<K, V>) -> Result<Option<Leaf<K, V>>, Collision> {
+        let key_path = leaf_to_add.get_key().key_path();
+        let mut parent = self.root;
+        let mut index: u8 = 0;
+        loop {
+            let direction = get_bit_direction(&key_path, index);
+            let child = self.get_mut_internal(parent).get_child(direction);
+            self.get_mut_internal(parent).my_hash = None;
+            index += 1;
+
+            if child.is_empty() {
+                let leaf_id = self.alloc(ArenaNode::Leaf(leaf_to_add));
+                self.get_mut_internal(parent).set_child(direction, leaf_id);
+                self.len += 1;
+                return Ok(None);
+            }
+            match &mut self.nodes[child.index()] {
+                ArenaNode::Internal(_) => parent = child,
+                ArenaNode::Leaf(n) if n.get_key() == leaf_to_add.get_key() => {
+                    return Ok(Some(std::mem::replace(n, leaf_to_add)));
+                }
+                ArenaNode::Leaf(n) => {
+                    let shared = shared_bits(&n.get_key().key_path(), &key_path);
+                    if index == 255 || shared >= 255 {
+                        return Err(Collision);
+                    }
+                    // the two Leaves are told apart at depth shared: build the chain of Internal
+                    // nodes leading there, from the deepest one up to the one at the current depth
+                    let new_direction = get_bit_direction(&key_path, shared as u8);
+                    let leaf_id = self.alloc(ArenaNode::Leaf(leaf_to_add));
+                    let mut branch = ArenaInternal::new(NodeId::EMPTY, NodeId::EMPTY);
+                    branch.set_child(new_direction, leaf_id);
+                    branch.set_child(!new_direction, child);
+                    let mut top = self.alloc(ArenaNode::Internal(branch));
+                    for depth in (index as usize..shared).rev() {
+                        let mut branch = ArenaInternal::new(NodeId::EMPTY, NodeId::EMPTY);
+                        branch.set_child(get_bit_direction(&key_path, depth as u8), top);
+                        top = self.alloc(ArenaNode::Internal(branch));
+                    }
+                    self.get_mut_internal(parent).set_child(direction, top);
+                    self.len += 1;
+                    return Ok(None);
+                }
+                ArenaNode::Vacant => panic!("Handle of a free slot!"),
+            }
+        }
+    }
+
+    /// Returns the value associated to the given key, or None if the key is not contained.
+    /// Removes the Leaf of the key from the ArenaMerkleTree, which is kept in its canonical
+    /// shape. The cached Hashes along the path are discarded.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        let key_path = key.key_path();
+        let mut path = vec![(self.root, get_bit_direction(&key_path, 0))];
+        let leaf_id = loop {
+            let (parent, direction) = *path.last().unwrap();
+            let child = self.get_mut_internal(parent).get_child(direction);
+            match self.get_arena_node(child) {
+                Some(ArenaNode::Internal(_)) => {
+                    path.push((child, get_bit_direction(&key_path, path.len() as u8)))
+                }
+                Some(ArenaNode::Leaf(n)) if n.get_key().borrow() == key => break child,
+                _ => return None,
+            }
+        };
+
+        let removed = match self.release(leaf_id) {
+            ArenaNode::Leaf(n) => n,
+            _ => panic!("Node which is not a Leaf!"),
+        };
+        self.len -= 1;
+
+        // every Internal node other than the root left with less than two Leaves is
+        // replaced by its only Leaf, or by an Empty node
+        let mut replacement = NodeId::EMPTY;
+        while let Some((parent, direction)) = path.pop() {
+            let internal = self.get_mut_internal(parent);
+            internal.my_hash = None;
+            internal.set_child(direction, replacement);
+            let (left, right) = (internal.left, internal.right);
+
+            if path.is_empty() || !(left.is_empty() || right.is_empty()) {
+                break;
+            }
+            let other = if left.is_empty() { right } else { left };
+            if let Some(ArenaNode::Internal(_)) = self.get_arena_node(other) {
+                break;
+            }
+            self.release(parent);
+            replacement = other;
+        }
+        for (parent, _) in path {
+            self.get_mut_internal(parent).my_hash = None;
+        }
+        Some(removed.get_value().clone())
+    }
+
+    /// Returns the Entry of the given key, to be read, inserted or modified in place.
+    /// The cached Hashes along the path are discarded only once a value is inserted
+    /// or modified through the Entry.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        match self.find_path(&key) {
+            Some(path) => {
+                let (leaf, path_hashes) = self.borrow_path_mut(path);
+                Entry::Occupied(OccupiedEntry::new(leaf, path_hashes))
+            }
+            None => Entry::Vacant(VacantEntry::from_arena(key, self)),
+        }
+    }
+
+    /// Returns a reference of the value associated to the given key,
+    /// or None if the key is not contained.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.get_leaf(key).map(|leaf| leaf.get_value())
+    }
+
+    /// Returns a mutable access to the value associated to the given key, or None if the key
+    /// is not contained. If the value is modified, the Hash of the Leaf is refreshed once the
+    /// access is dropped, and the cached Hashes along the path are discarded.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<ValueMut<'_, K, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.get_path_mut(key)
+            .map(|(leaf, path_hashes)| ValueMut::new(leaf, path_hashes))
+    }
+
+    /// Returns true if the given key is contained in the ArenaMerkleTree.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.get_leaf(key).is_some()
+    }
+
+    /// Returns the number of entries of the ArenaMerkleTree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the ArenaMerkleTree holds no entry.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a reference of the salt of the Leaf associated to the given key,
+    /// or None if the Leaf is not salted or the key is not contained.
+    pub fn get_salt<Q>(&self, key: &Q) -> Option<&Hash>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.get_leaf(key).and_then(|leaf| leaf.get_salt())
+    }
+
+    /// Returns a reference of the value associated to the given key.
+    /// Panics if the given key is not associated to any value in the ArenaMerkleTree.
+    pub fn get_value<Q>(&self, key: &Q) -> &V
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.get(key).unwrap()
+    }
+
+    /// Returns the hash of the root of the ArenaMerkleTree. Computes and assigns the
+    /// corresponding Hash to every Internal node whose cached Hash was discarded.
+    pub fn compute_hashes(&mut self) -> Hash {
+        self.compute_hash(self.root)
+    }
+
+    /// Returns a Proof for the specific given key. Only the Hashes discarded since the last
+    /// call to compute_hashes are computed again, as compute_hashes does.
+    /// The Proof contains an empty vector of Siblings if the key is not contained.
+    pub fn prove(&mut self, key: K) -> Proof {
+        self.compute_hashes();
+        match self.try_prove(key) {
+            Ok(proof) => proof,
+            Err(StaleHashes) => unreachable!("the Hashes have just been computed"),
+        }
+    }
+
+    /// Returns a Result which contains: a Proof for the specific given key, as prove does;
+    /// Err(StaleHashes) if the Hash of an Internal node needed by the Proof has been discarded
+    /// by a modification since the last call to compute_hashes.
+    pub fn try_prove(&self, key: K) -> Result<Proof, StaleHashes> {
+        if !self.contains_key(&key) {
+            return Ok(Proof::new(Vec::new()));
+        }
+        let key_path = key.key_path();
+        let mut siblings = Vec::<Sibling>::new();
+        let mut index = 0;
+        let mut id = self.root;
+        while let Some(ArenaNode::Internal(n)) = self.get_arena_node(id) {
+            let direction = get_bit_direction(&key_path, index);
+            let sibling_hash = self.get_cached_hash(n.get_child(!direction))?;
+            let sibling_direction = if direction {
+                Left {}.into()
+            } else {
+                Right {}.into()
+            };
+            siblings.push(Sibling::new(sibling_hash, sibling_direction));
+            id = n.get_child(direction);
+            index += 1;
+        }
+        siblings.reverse();
+        Ok(Proof::new(siblings))
+    }
+
+    /// Returns an OpenedProof for the specific given key, carrying the key, the value and the
+    /// salt of its Leaf together with the unsigned Hash of the root, or None if the key is not contained.
+    /// Only the Hashes discarded since the last call to compute_hashes are computed again.
+    pub fn prove_opened<Q>(&mut self, key: &Q) -> Option<OpenedProof<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.compute_hashes();
+        let leaf = self.get_leaf(key)?;
+        let proof = match self.try_prove(leaf.get_key().clone()) {
+            Ok(proof) => proof,
+            Err(StaleHashes) => unreachable!("the Hashes have just been computed"),
+        };
+        Some(OpenedProof::new(
+            leaf.get_key().clone(),
+            leaf.get_value().clone(),
+            leaf.get_salt().cloned(),
+            proof.get_siblings().clone(),
+            self.get_cached_hash(self.root).ok(),
+        ))
+    }
+
+    /// Returns true if the ArenaMerkleTree has been modified since the last call to compute_hashes,
+    /// so that some Hashes are to be computed again before try_prove can generate a Proof.
+    pub fn has_stale_hashes(&self) -> bool {
+        self.get_cached_hash(self.root).is_err()
+    }
+
+    /// Returns a Proof for the specific given key.
+    /// Sequentially invokes the methods:
+    /// compute_hashes(&mut self) -> Hash  and
+    /// prove(&mut self, key: K) -> Proof
+    /// The Proof contains an empty vector of Siblings if the key is not contained.
+    pub fn compute_hashes_prove(&mut self, key: K) -> Proof {
+        self.compute_hashes();
+        self.prove(key)
+    }
+
+    /// Returns the hash of the root of the ArenaMerkleTree, bit-identical to the one returned
+    /// by compute_hashes. The two subtrees of every Internal node less deep than the given split
+    /// depth are hashed on separate threads, so that at most 2^split_depth threads are used.
+    pub fn compute_hashes_parallel(&mut self, split_depth: u8) -> Hash
+    where
+        K: Sync,
+        V: Sync,
+    {
+        // the threads only read the arena: the Hashes they compute are assigned afterwards
+        let mut computed = Vec::<(NodeId, Hash)>::new();
+        let root_hash = self.collect_hashes(self.root, split_depth, &mut computed);
+        for (id, this_hash) in computed {
+            self.get_mut_internal(id).my_hash = Some(this_hash);
+        }
+        root_hash
+    }
+
+    /// Returns a RangeProof for every key whose hash starts with the given prefix of bits
+    /// (true <--> Right, false <--> Left). Salted Leaves are given by their Hash only.
+    /// Returns Err(RangeError::PrefixTooLong) if the prefix is longer than MAX_PREFIX_LEN bits.
+    /// Only the Hashes discarded since the last call to compute_hashes are computed again.
+    pub fn prove_range(&mut self, prefix_bits: &[bool]) -> Result<RangeProof<K, V>, RangeError> {
+        self.compute_hashes();
+        match self.try_prove_range(prefix_bits) {
+            Err(RangeError::StaleHashes) => unreachable!("the Hashes have just been computed"),
+            result => result,
+        }
+    }
+
+    /// Returns a Result which contains: a RangeProof for the given prefix of bits, as
+    /// prove_range does; Err(RangeError::PrefixTooLong) if the prefix is longer than
+    /// MAX_PREFIX_LEN bits; Err(RangeError::StaleHashes) if the Hash of an Internal node needed
+    /// by the RangeProof has been discarded by a modification since the last call to compute_hashes.
+    pub fn try_prove_range(&self, prefix_bits: &[bool]) -> Result<RangeProof<K, V>, RangeError> {
+        if prefix_bits.len() > MAX_PREFIX_LEN {
+            return Err(RangeError::PrefixTooLong);
+        }
+        let mut siblings = Vec::<Sibling>::new();
+        let mut id = self.root;
+        for direction in prefix_bits.iter() {
+            let n = match self.get_arena_node(id) {
+                Some(ArenaNode::Internal(n)) => n,
+                _ => break,
+            };
+            let sibling_hash = self.get_cached_hash(n.get_child(!*direction))?;
+            let sibling_direction = if *direction {
+                Left {}.into()
+            } else {
+                Right {}.into()
+            };
+            siblings.push(Sibling::new(sibling_hash, sibling_direction));
+            id = n.get_child(*direction);
+        }
+        siblings.reverse();
+
+        let mut leaves = Vec::<Leaf<K, V>>::new();
+        self.get_leaves(id, &mut leaves);
+        let leaves = leaves.iter().map(RangeLeaf::from_leaf).collect();
+        Ok(RangeProof::new(prefix_bits.to_vec(), leaves, siblings))
+    }
+
+    /// Returns the Changes turning the ArenaMerkleTree into the given one. Branches whose
+    /// cached Hashes are equal are skipped without being visited, as MerkleTree::diff does.
+    pub fn diff(&self, other: &ArenaMerkleTree<K, V>) -> Vec<Change<K, V>> {
+        let mut changes = Vec::<Change<K, V>>::new();
+        self.diff_nodes(self.root, other, other.root, &mut changes);
+        changes
+    }
+
+    /// Returns true if the ArenaMerkleTree is in its canonical shape, as MerkleTree::is_canonical.
+    pub fn is_canonical(&self) -> bool {
+        self.find_violations().is_empty()
+    }
+
+    /// Returns every breach of the canonical shape of the ArenaMerkleTree.
+    pub fn find_violations(&self) -> Vec<Violation> {
+        let mut violations = Vec::<Violation>::new();
+        match self.get_arena_node(self.root) {
+            Some(ArenaNode::Internal(_)) => (),
+            _ => violations.push(Violation::RootNotInternal),
+        }
+        self.find_node_violations(self.root, &mut Vec::new(), &mut violations);
+        violations
+    }
+
+    /// Returns the independent subtrees of the ArenaMerkleTree found at the given split depth,
+    /// as MerkleTree::split_at_prefix does. The subtrees keep the cached Hashes of their nodes.
+    pub fn split_at_prefix(self, split_depth: u8) -> Vec<(Vec<bool>, NodeGeneric<K, V>)> {
+        let mut subtrees = Vec::<(Vec<bool>, NodeGeneric<K, V>)>::new();
+        self.get_subtrees(self.root, split_depth, &mut Vec::new(), &mut subtrees);
+        subtrees
+    }
+
+    /// Returns Ok(()) once every Leaf whose key hash starts with the given prefix of bits has
+    /// been replaced by the Leaves of the given subtree, possibly computed on another machine.
+    /// Returns Err(()), leaving the ArenaMerkleTree untouched, if a Leaf of the subtree does not
+    /// belong under the prefix or if two of its Leaves share the same key or collide.
+    pub fn graft(&mut self, prefix: &[bool], subtree: NodeGeneric<K, V>) -> Result<(), ()> {
+        let mut leaves = Vec::<Leaf<K, V>>::new();
+        subtree.into_leaves(&mut leaves);
+        self.replace_range(prefix, leaves)
+    }
+
+    /// Returns Ok(()) once every Leaf whose key hash starts with the given prefix of bits has
+    /// been replaced by the given Leaves. Returns Err(()), leaving the ArenaMerkleTree untouched,
+    /// if a given Leaf does not belong under the prefix or if two of them share the same key
+    /// or collide.
+    pub fn replace_range(&mut self, prefix: &[bool], leaves: Vec<Leaf<K, V>>) -> Result<(), ()> {
+        let mut paths = HashSet::<[u8; 32]>::new();
+        for leaf in leaves.iter() {
+            let mut path = leaf.get_key().key_path();
+            // the last bit of the path of a key is never followed
+            path[31] &= 0xfe;
+            if !starts_with_prefix(&path, prefix) || !paths.insert(path) {
+                return Err(());
+            }
+        }
+
+        let mut id = self.root;
+        for direction in prefix.iter() {
+            match self.get_arena_node(id) {
+                Some(ArenaNode::Internal(n)) => id = n.get_child(*direction),
+                _ => break,
+            }
+        }
+        let mut old_leaves = Vec::<Leaf<K, V>>::new();
+        self.get_leaves(id, &mut old_leaves);
+        for old in old_leaves {
+            // the path may end before the prefix, on a Leaf lying outside of it
+            if starts_with_prefix(&old.get_key().key_path(), prefix) {
+                self.remove(old.get_key());
+            }
+        }
+        for leaf in leaves {
+            self.insert_leaf(leaf);
+        }
+        Ok(())
+    }
+
+    /// Returns the Leaf associated to the given key, or None if the key is not contained.
+    fn get_leaf<Q>(&self, key: &Q) -> Option<&Leaf<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        let key_path = key.key_path();
+        let mut index = 0;
+        let mut id = self.root;
+        loop {
+            match self.get_arena_node(id) {
+                Some(ArenaNode::Internal(n)) => {
+                    id = n.get_child(get_bit_direction(&key_path, index));
+                    index += 1;
+                }
+                Some(ArenaNode::Leaf(n)) if n.get_key().borrow() == key => return Some(n),
+                _ => return None,
+            }
+        }
+    }
+
+    /// Returns the Leaf associated to the given key, together with the cached Hashes of the
+    /// Internal nodes along the path leading to it, or None if the key is not contained.
+    fn get_path_mut<Q>(&mut self, key: &Q) -> Option<LeafPathMut<'_, K, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        let path = self.find_path(key)?;
+        Some(self.borrow_path_mut(path))
+    }
+
+    /// Returns the handles of the Internal nodes along the path leading to the Leaf associated
+    /// to the given key, followed by the handle of the Leaf, or None if the key is not contained.
+    fn find_path<Q>(&self, key: &Q) -> Option<Vec<NodeId>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        let key_path = key.key_path();
+        let mut path = Vec::<NodeId>::new();
+        let mut id = self.root;
+        while let Some(ArenaNode::Internal(n)) = self.get_arena_node(id) {
+            path.push(id);
+            id = n.get_child(get_bit_direction(&key_path, path.len() as u8 - 1));
+        }
+        match self.get_arena_node(id) {
+            Some(ArenaNode::Leaf(n)) if n.get_key().borrow() == key => (),
+            _ => return None,
+        }
+        path.push(id);
+        Some(path)
+    }
+
+    /// Returns the Leaf at the end of the given path returned by find_path, together
+    /// with the cached Hashes of the Internal nodes along it.
+    fn borrow_path_mut(&mut self, path: Vec<NodeId>) -> LeafPathMut<'_, K, V> {
+        let mut leaf = None;
+        let mut path_hashes = Vec::<&mut Option<Hash>>::new();
+        for node in self.get_many_mut(path) {
+            match node {
+                ArenaNode::Internal(n) => path_hashes.push(&mut n.my_hash),
+                ArenaNode::Leaf(n) => leaf = Some(n),
+                ArenaNode::Vacant => panic!("Handle of a free slot!"),
+            }
+        }
+        (leaf.expect("Path which does not end on a Leaf!"), path_hashes)
+    }
+
+    /// Appends a copy of every Leaf in the subtree of the given handle to the given vector,
+    /// from the leftmost to the rightmost.
+    fn get_leaves(&self, id: NodeId, leaves: &mut Vec<Leaf<K, V>>) {
+        match self.get_arena_node(id) {
+            None => (),
+            Some(ArenaNode::Leaf(n)) => leaves.push(n.clone()),
+            Some(ArenaNode::Internal(n)) => {
+                self.get_leaves(n.left, leaves);
+                self.get_leaves(n.right, leaves);
+            }
+            Some(ArenaNode::Vacant) => panic!("Handle of a free slot!"),
+        }
+    }
+
+    /// Returns the subtree of the given handle as a NodeGeneric, cached Hashes included.
+    fn to_node(&self, id: NodeId) -> NodeGeneric<K, V> {
+        match self.get_arena_node(id) {
+            None => Empty::new().into(),
+            Some(ArenaNode::Leaf(n)) => n.clone().into(),
+            Some(ArenaNode::Internal(n)) => {
+                Internal::new(self.to_node(n.left), self.to_node(n.right), n.my_hash).into()
+            }
+            Some(ArenaNode::Vacant) => panic!("Handle of a free slot!"),
+        }
+    }
+
+    /// Recursively appends to the given vector the Changes turning the subtree of the given
+    /// handle into the subtree of the given handle of the other ArenaMerkleTree, at the same depth.
+    fn diff_nodes(&self, id: NodeId, other: &ArenaMerkleTree<K, V>, other_id: NodeId, changes: &mut Vec<Change<K, V>>) {
+        match (self.get_cached_hash(id), other.get_cached_hash(other_id)) {
+            (Ok(h1), Ok(h2)) if h1 == h2 => return,
+            _ => (),
+        }
+        if let (Some(ArenaNode::Internal(n1)), Some(ArenaNode::Internal(n2))) =
+            (self.get_arena_node(id), other.get_arena_node(other_id))
+        {
+            self.diff_nodes(n1.left, other, n2.left, changes);
+            self.diff_nodes(n1.right, other, n2.right, changes);
+            return;
+        }
+
+        // at least one side holds at most one Leaf, so pairing the Leaves is cheap
+        let mut old_leaves = Vec::<Leaf<K, V>>::new();
+        let mut new_leaves = Vec::<Leaf<K, V>>::new();
+        self.get_leaves(id, &mut old_leaves);
+        other.get_leaves(other_id, &mut new_leaves);
+        Leaf::diff_leaves(old_leaves, new_leaves, changes);
+    }
+
+    /// Returns the number of Leaves in the subtree of the given handle, together with its Hash.
+    /// Every breach of the canonical shape met in the subtree is appended to the given vector
+    /// of Violations. The given path leads from the root to the node of the given handle.
+    fn find_node_violations(&self, id: NodeId, path: &mut Vec<bool>, violations: &mut Vec<Violation>) -> (usize, Hash) {
+        match self.get_arena_node(id) {
+            None => (0, Empty::get_hash()),
+            Some(ArenaNode::Leaf(n)) => {
+                if !starts_with_prefix(&n.get_key().key_path(), path) {
+                    violations.push(Violation::MisplacedLeaf(path.clone()));
+                }
+                (1, n.get_hash())
+            }
+            Some(ArenaNode::Internal(n)) => {
+                path.push(false);
+                let (l_count, l_hash) = self.find_node_violations(n.left, path, violations);
+                path.pop();
+                path.push(true);
+                let (r_count, r_hash) = self.find_node_violations(n.right, path, violations);
+                path.pop();
+
+                let this_hash = Internal::<K, V>::create_hash(l_hash, r_hash);
+                // only the root is allowed to hold less than two Leaves
+                if !path.is_empty() && l_count + r_count < 2 {
+                    violations.push(Violation::NonMinimalInternal(path.clone()));
+                }
+                if let Some(h) = n.my_hash {
+                    if h != this_hash {
+                        violations.push(Violation::StaleHash(path.clone()));
+                    }
+                }
+                (l_count + r_count, this_hash)
+            }
+            Some(ArenaNode::Vacant) => panic!("Handle of a free slot!"),
+        }
+    }
+
+    /// Appends every non-empty subtree found at the given split depth to the given vector,
+    /// together with the prefix of bits leading to it. A Leaf less deep than the split depth
+    /// is given as a subtree of its own, under the prefix given by the hash of its key.
+    /// The given path leads from the root to the node of the given handle.
+    fn get_subtrees(
+        &self,
+        id: NodeId,
+        split_depth: u8,
+        path: &mut Vec<bool>,
+        subtrees: &mut Vec<(Vec<bool>, NodeGeneric<K, V>)>,
+    ) {
+        if path.len() == split_depth as usize {
+            if !id.is_empty() {
+                subtrees.push((path.clone(), self.to_node(id)));
+            }
+            return;
+        }
+        match self.get_arena_node(id) {
+            None => (),
+            Some(ArenaNode::Leaf(n)) => {
+                let key_path = n.get_key().key_path();
+                let prefix = (0..split_depth).map(|i| get_bit_direction(&key_path, i)).collect();
+                subtrees.push((prefix, n.clone().into()));
+            }
+            Some(ArenaNode::Internal(n)) => {
+                path.push(false);
+                self.get_subtrees(n.left, split_depth, path, subtrees);
+                path.pop();
+                path.push(true);
+                self.get_subtrees(n.right, split_depth, path, subtrees);
+                path.pop();
+            }
+            Some(ArenaNode::Vacant) => panic!("Handle of a free slot!"),
+        }
+    }
+
+    /// Returns the Hash of the node of the given handle, appending to the given vector the
+    /// Hashes of the Internal nodes of its subtree whose cached Hash was discarded, without
+    /// assigning them. The subtrees less deep than the given split depth are hashed on
+    /// separate threads.
+    fn collect_hashes(&self, id: NodeId, split_depth: u8, computed: &mut Vec<(NodeId, Hash)>) -> Hash
+    where
+        K: Sync,
+        V: Sync,
+    {
+        let n = match self.get_arena_node(id) {
+            Some(ArenaNode::Internal(n)) => n,
+            _ => return self.get_cached_hash(id).unwrap(),
+        };
+        if let Some(h) = n.my_hash {
+            return h;
+        }
+        let both_internal = matches!(
+            (self.get_arena_node(n.left), self.get_arena_node(n.right)),
+            (Some(ArenaNode::Internal(_)), Some(ArenaNode::Internal(_)))
+        );
+
+        let (l_hash, r_hash) = if split_depth == 0 || !both_internal {
+            // not worth a thread: keep descending on the current one
+            (
+                self.collect_hashes(n.left, split_depth.saturating_sub(1), computed),
+                self.collect_hashes(n.right, split_depth.saturating_sub(1), computed),
+            )
+        } else {
+            std::thread::scope(|s| {
+                let l_handle = s.spawn(|| {
+                    let mut l_computed = Vec::<(NodeId, Hash)>::new();
+                    let l_hash = self.collect_hashes(n.left, split_depth - 1, &mut l_computed);
+                    (l_hash, l_computed)
+                });
+                let r_hash = self.collect_hashes(n.right, split_depth - 1, computed);
+                let (l_hash, l_computed) = l_handle.join().unwrap();
+                computed.extend(l_computed);
+                (l_hash, r_hash)
+            })
+        };
+        let this_hash = Internal::<K, V>::create_hash(l_hash, r_hash);
+        computed.push((id, this_hash));
+        this_hash
+    }
+
+    /// Returns the Hash of the node of the given handle, computing the Hashes of the Internal
+    /// nodes of its subtree whose cached Hash was discarded.
+    fn compute_hash(&mut self, id: NodeId) -> Hash {
+        let (left, right) = match self.get_arena_node(id) {
+            None => return Empty::get_hash(),
+            Some(ArenaNode::Leaf(n)) => return n.get_hash(),
+            Some(ArenaNode::Internal(n)) => match n.my_hash {
+                Some(h) => return h,
+                None => (n.left, n.right),
+            },
+            Some(ArenaNode::Vacant) => panic!("Handle of a free slot!"),
+        };
+        let this_hash = Internal::<K, V>::create_hash(self.compute_hash(left), self.compute_hash(right));
+        self.get_mut_internal(id).my_hash = Some(this_hash);
+        this_hash
+    }
+
+    /// Returns the cached Hash of the node of the given handle, or Err(StaleHashes)
+    /// if the Hash of an Internal node has not been computed.
+    fn get_cached_hash(&self, id: NodeId) -> Result<Hash, StaleHashes> {
+        match self.get_arena_node(id) {
+            None => Ok(Empty::get_hash()),
+            Some(ArenaNode::Leaf(n)) => Ok(n.get_hash()),
+            Some(ArenaNode::Internal(n)) => n.my_hash.ok_or(StaleHashes),
+            Some(ArenaNode::Vacant) => panic!("Handle of a free slot!"),
+        }
+    }
+
+    /// Returns a mutable reference to the Internal node of the given handle.
+    /// Panics if the node is not Internal.
+    fn get_mut_internal(&mut self, id: NodeId) -> &mut ArenaInternal {
+        match &mut self.nodes[id.index()] {
+            ArenaNode::Internal(n) => n,
+            _ => panic!("Node which is not a Internal!"),
+        }
+    }
+
+    /// Returns mutable references to the nodes of the given distinct handles, in the order
+    /// of their slots.
+    fn get_many_mut(&mut self, mut ids: Vec<NodeId>) -> Vec<&mut ArenaNode<K, V>> {
+        ids.sort_by_key(|id| id.index());
+        let mut nodes = Vec::with_capacity(ids.len());
+        let mut rest = &mut self.nodes[..];
+        let mut offset = 0;
+        for id in ids {
+            let (_, tail) = std::mem::take(&mut rest).split_at_mut(id.index() - offset);
+            let (node, tail) = tail.split_first_mut().unwrap();
+            nodes.push(node);
+            rest = tail;
+            offset = id.index() + 1;
+        }
+        nodes
+    }
+
+    /// Returns the handle of the given node, stored in a free slot if there is one.
+    fn alloc(&mut self, node: ArenaNode<K, V>) -> NodeId {
+        match self.free.pop() {
+            Some(id) => {
+                self.nodes[id.index()] = node;
+                id
+            }
+            None => {
+                assert!(self.nodes.len() < u32::MAX as usize, "arena of an ArenaMerkleTree is full");
+                self.nodes.push(node);
+                NodeId(self.nodes.len() as u32 - 1)
+            }
+        }
+    }
+
+    /// Returns the node of the given handle, whose slot is freed.
+    fn release(&mut self, id: NodeId) -> ArenaNode<K, V> {
+        self.free.push(id);
+        std::mem::replace(&mut self.nodes[id.index()], ArenaNode::Vacant)
+    }
+}
+
+impl<K, V> Default for ArenaMerkleTree<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    fn default() -> Self {
+        ArenaMerkleTree::new()
+    }
+}
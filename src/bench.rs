@@ -0,0 +1,153 @@
+use crate::client_verify::*;
+use crate::hexary::*;
+use crate::mpt::*;
+use crate::node_generic::*;
+use crate::structs::*;
+use crate::util::*;
+use serde::Serialize;
+use std::mem::size_of;
+use std::time::Instant;
+use talk::crypto::primitives::hash::Hash;
+
+/*
+ * Benchmark harness for insert, compute_hashes, prove and get_root_hash, run over synthetic
+ * batches of u64 keys and values with no external service involved. Every measurement is
+ * printed on its own line as a JSON object, so that the output of two commits can be diffed
+ * or loaded for comparison. Run with:
+ *     cargo run --release -- bench [max_keys]
+*/
+
+const BATCH_SIZES: [usize; 4] = [1_000, 10_000, 100_000, 1_000_000];
+const MAX_PROVEN_KEYS: usize = 10_000;
+
+/// A single measurement of the benchmark harness.
+#[derive(Serialize, Debug, Clone)]
+pub struct BenchResult {
+    operation: &'static str,
+    keys: usize,
+    ops: usize,
+    seconds: f64,
+    ops_per_second: f64,
+    memory_bytes: usize,
+    avg_proof_bytes: Option<f64>,
+}
+
+impl BenchResult {
+    /// Returns a new BenchResult, given the number of operations run in the given number of seconds.
+    pub fn new(operation: &'static str, keys: usize, ops: usize, seconds: f64, memory_bytes: usize) -> Self {
+        BenchResult {
+            operation,
+            keys,
+            ops,
+            seconds,
+            ops_per_second: ops as f64 / seconds.max(f64::EPSILON),
+            memory_bytes,
+            avg_proof_bytes: None,
+        }
+    }
+
+    /// Returns the name of the measured operation.
+    pub fn get_operation(&self) -> &str {
+        self.operation
+    }
+
+    /// Returns the average size of the proofs in bytes, if the operation deals with proofs.
+    pub fn get_avg_proof_bytes(&self) -> Option<f64> {
+        self.avg_proof_bytes
+    }
+}
+
+/// Runs the benchmark over every batch size up to the given number of keys,
+/// printing each BenchResult as a line of JSON.
+pub fn run(max_keys: usize) {
+    for keys in BATCH_SIZES.iter().filter(|keys| **keys <= max_keys) {
+        for result in run_batch(*keys) {
+            println!("{}", serde_json::to_string(&result).unwrap());
+        }
+    }
+}
+
+/// Returns the BenchResults of a synthetic batch holding the given number of keys.
+pub fn run_batch(keys: usize) -> Vec<BenchResult> {
+    let mut results = Vec::<BenchResult>::new();
+    let proven = keys.min(MAX_PROVEN_KEYS);
+
+    let start = Instant::now();
+    let mut mpt: MerkleTree<u64, u64> = MerkleTree::new();
+    for i in 0..keys as u64 {
+        mpt.insert(i, i);
+    }
+    let memory_bytes = estimate_memory(mpt.get_root());
+    results.push(BenchResult::new("insert", keys, keys, elapsed(start), memory_bytes));
+
+    let start = Instant::now();
+    let root_hash = mpt.compute_hashes();
+    results.push(BenchResult::new("compute_hashes", keys, 1, elapsed(start), memory_bytes));
+
+    let start = Instant::now();
+    let proofs: Vec<Proof> = (0..proven as u64).map(|i| mpt.prove(i)).collect();
+    let mut result = BenchResult::new("prove", keys, proven, elapsed(start), memory_bytes);
+    result.avg_proof_bytes = Some(average_proof_bytes(&proofs));
+    results.push(result);
+
+    let start = Instant::now();
+    for (i, proof) in proofs.into_iter().enumerate() {
+        assert_eq!(get_root_hash(proof, i as u64, Id::new(i as u64)), Ok(root_hash));
+    }
+    results.push(BenchResult::new("verify", keys, proven, elapsed(start), memory_bytes));
+
+    let mut hexary: HexaryMerkleTree<u64, u64> = HexaryMerkleTree::new();
+    for i in 0..keys as u64 {
+        hexary.insert(i, i);
+    }
+    hexary.compute_hashes();
+    let memory_bytes = estimate_hexary_memory(hexary.get_root());
+    let start = Instant::now();
+    let hexary_proofs: Vec<HexaryProof> = (0..proven as u64).map(|i| hexary.prove(&i)).collect();
+    let mut result = BenchResult::new("prove_hexary", keys, proven, elapsed(start), memory_bytes);
+    result.avg_proof_bytes = Some(average_proof_bytes(&hexary_proofs));
+    results.push(result);
+
+    results
+}
+
+/// Returns an estimate, in bytes, of the memory held by the given node and its subtree:
+/// every node is boxed, and its size is the one of the largest variant of NodeGeneric.
+/// The memory owned by keys and values on the heap, if any, is not taken into account.
+pub fn estimate_memory<K, V>(node: &NodeGeneric<K, V>) -> usize
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    let children = match node {
+        NodeGeneric::Internal(n) => estimate_memory(n.get_left()) + estimate_memory(n.get_right()),
+        _ => 0,
+    };
+    size_of::<NodeGeneric<K, V>>() + children
+}
+
+/// Returns an estimate, in bytes, of the memory held by the given node of a HexaryMerkleTree
+/// and its subtree: the 16 children of every Branch are stored next to each other.
+pub fn estimate_hexary_memory<K, V>(node: &HexaryNode<K, V>) -> usize
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    let children = match node {
+        HexaryNode::Branch(n) => n.get_children().iter().map(estimate_hexary_memory).sum(),
+        _ => 0,
+    };
+    size_of::<HexaryNode<K, V>>() + children
+}
+
+/// Returns the average size in bytes of the given proofs, counting the Hashes they carry
+/// only: the direction of a Sibling, or the position in a HexaryLevel, fits in a few bits.
+fn average_proof_bytes<P: MerkleProof>(proofs: &[P]) -> f64 {
+    let hashes: usize = proofs.iter().map(|proof| proof.get_hash_count()).sum();
+    (hashes * size_of::<Hash>()) as f64 / proofs.len().max(1) as f64
+}
+
+/// Returns the number of seconds elapsed since the given Instant.
+fn elapsed(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64()
+}
@@ -1,17 +1,273 @@
-use crate::{node_generic::*, structs::*};
+use crate::{node_generic::*, structs::*, util::*};
 use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use talk::crypto::primitives::hash::{hash, Hash};
 
-/// Returns the Hash of the root, computed according to the given proof.
-pub fn get_root_hash<T, K>(proof: Proof, my_transactions: T, id: Id<K>) -> Hash
+/// Returns a Result which contains: the Hash of the root, computed according to the given
+/// proof; Err(()) if the Siblings of the proof do not follow the path of the given key.
+pub fn get_root_hash<T, K>(proof: Proof, my_transactions: T, id: Id<K>) -> Result<Hash, ()>
 where
     T: Serialize + Clone,
-    K: Serialize + Eq + Clone,
+    K: Serialize + KeyPath + Eq + Clone,
 {
-    let siblings = proof.get_siblings();
     let my_leaf = Leaf::<K, T>::new(id.get_key().clone(), my_transactions);
+    fold_path(proof.get_siblings(), &id.get_key().key_path(), my_leaf.get_hash())
+}
+
+/// Returns a Result which contains: the Hash of the root, computed according to the given
+/// proof of a salted Leaf; Err(()) if the Siblings of the proof do not follow the path of
+/// the given key. The salt is revealed by the broker to the owner of the Leaf only.
+pub fn get_root_hash_salted<T, K>(proof: Proof, my_transactions: T, id: Id<K>, salt: Hash) -> Result<Hash, ()>
+where
+    T: Serialize + Clone,
+    K: Serialize + KeyPath + Eq + Clone,
+{
+    let my_leaf = Leaf::<K, T>::new_salted(id.get_key().clone(), my_transactions, salt);
+    fold_path(proof.get_siblings(), &id.get_key().key_path(), my_leaf.get_hash())
+}
+
+/// Returns the Hash of the root, computed according to the given proof from the given Hash
+/// of the proven Leaf, as returned by Leaf::create_leaf_hash or Leaf::create_salted_leaf_hash.
+/// The key being unknown, the position of the Leaf is not checked against its path.
+pub fn get_root_hash_from_leaf_hash(proof: Proof, leaf_hash: Hash) -> Hash {
+    fold_siblings(proof.get_siblings(), leaf_hash)
+}
+
+/// Returns a Result which contains: the Hash of the root, computed according to the given
+/// proof from the given Hash of the value of the proven Leaf; Err(()) if the Siblings of the
+/// proof do not follow the path of the given key. The value itself is neither needed nor rehashed.
+pub fn get_root_hash_from_value_hash<K>(proof: Proof, id: Id<K>, value_hash: Hash) -> Result<Hash, ()>
+where
+    K: Serialize + KeyPath + Eq + Clone,
+{
+    let key_hash = hash(id.get_key()).unwrap();
+    let leaf_hash = Leaf::<K, ()>::create_leaf_hash_from(key_hash, value_hash);
+    fold_path(proof.get_siblings(), &id.get_key().key_path(), leaf_hash)
+}
+
+/// Returns a Result which contains: the Hash of the root, computed according to the given
+/// proof of a salted Leaf from the given Hash of its value and its salt; Err(()) if the
+/// Siblings of the proof do not follow the path of the given key.
+pub fn get_root_hash_salted_from_value_hash<K>(proof: Proof, id: Id<K>, value_hash: Hash, salt: Hash) -> Result<Hash, ()>
+where
+    K: Serialize + KeyPath + Eq + Clone,
+{
+    let key_hash = hash(id.get_key()).unwrap();
+    let leaf_hash = Leaf::<K, ()>::create_salted_leaf_hash_from(key_hash, value_hash, salt);
+    fold_path(proof.get_siblings(), &id.get_key().key_path(), leaf_hash)
+}
+
+/// Verifier of many Proofs against the same root. The Hash of every Internal node computed
+/// along a Proof is remembered, so that the upper levels shared by the Proofs of a batch are
+/// hashed once: each Proof passes or fails exactly as it would on its own.
+pub struct BatchVerifier {
+    root_hash: Hash,
+    parents: HashMap<(Hash, Hash), Hash>,
+    hash_count: usize,
+}
+
+impl BatchVerifier {
+    /// Returns a new BatchVerifier for the given Hash of the root.
+    pub fn new(root_hash: Hash) -> Self {
+        BatchVerifier {
+            root_hash,
+            parents: HashMap::new(),
+            hash_count: 0,
+        }
+    }
+
+    /// Returns true if the given proof leads from the Leaf holding the given
+    /// transactions, associated to the given Id, to the root of the BatchVerifier,
+    /// following the path of the key of the Id.
+    pub fn verify<T, K>(&mut self, proof: &Proof, my_transactions: T, id: Id<K>) -> bool
+    where
+        T: Serialize + Clone,
+        K: Serialize + KeyPath + Eq + Clone,
+    {
+        if !proof.follows_path(&id.get_key().key_path()) {
+            return false;
+        }
+        let my_leaf = Leaf::<K, T>::new(id.get_key().clone(), my_transactions);
+        let mut hash_final = my_leaf.get_hash();
+
+        for sibling in proof.get_siblings() {
+            let children = match sibling.get_direction() {
+                Direction::Left => (*sibling.get_hash(), hash_final),
+                Direction::Right => (hash_final, *sibling.get_hash()),
+            };
+            let hash_count = &mut self.hash_count;
+            hash_final = *self.parents.entry(children).or_insert_with(|| {
+                *hash_count += 1;
+                Internal::<(), ()>::create_hash(children.0, children.1)
+            });
+        }
+        hash_final == self.root_hash
+    }
+
+    /// Returns the number of Internal node Hashes computed so far, each shared one counted once.
+    pub fn get_hash_count(&self) -> usize {
+        self.hash_count
+    }
+}
+
+/// Returns, for each of the given proofs, together with the transactions and the Id of the
+/// Leaf it proves, true if it leads to the given Hash of the root. Upper levels shared among
+/// the proofs are hashed once.
+pub fn verify_batch<T, K, I>(root_hash: Hash, items: I) -> Vec<bool>
+where
+    T: Serialize + Clone,
+    K: Serialize + KeyPath + Eq + Clone,
+    I: IntoIterator<Item = (Proof, T, Id<K>)>,
+{
+    let mut verifier = BatchVerifier::new(root_hash);
+    items
+        .into_iter()
+        .map(|(proof, my_transactions, id)| verifier.verify(&proof, my_transactions, id))
+        .collect()
+}
+
+/// Returns a Result which contains: the Hash of the root, computed according to the given proof,
+/// issued by either a binary or a hexary tree; Err(()) if the proof is malformed.
+pub fn get_proof_root_hash<P, T, K>(proof: &P, my_transactions: T, id: Id<K>) -> Result<Hash, ()>
+where
+    P: MerkleProof,
+    T: Serialize + Clone,
+    K: Serialize + KeyPath + Eq + Clone,
+{
+    if !proof.follows_path(&id.get_key().key_path()) {
+        return Err(());
+    }
+    let my_leaf = Leaf::<K, T>::new(id.get_key().clone(), my_transactions);
+    proof.get_root_hash_from(my_leaf.get_hash())
+}
+
+/// Returns the Hash of the root, computed according to the given RangeProof. Every Leaf 
+/// below the proven prefix takes part in the computation, so that omitting one of them
+/// yields a different Hash. The Hash of a sealed Leaf is taken as given, while the one of
+/// an opened Leaf is recomputed. Returns Err(()) if the RangeProof is malformed, including
+/// when its prefix is longer than MAX_PREFIX_LEN bits.
+pub fn get_range_root_hash<K, V>(range_proof: &RangeProof<K, V>) -> Result<Hash, ()>
+where
+    K: Serialize + KeyPath + Eq + Clone,
+    V: Serialize + Clone,
+{
+    let prefix = range_proof.get_prefix();
+    let siblings = range_proof.get_siblings();
+    let depth = siblings.len();
+    if prefix.len() > MAX_PREFIX_LEN || depth > prefix.len() || (depth < prefix.len() && range_proof.get_leaves().len() > 1) {
+        return Err(());
+    }
+
+    // the Siblings go from the deepest one to the one of the root, and each of them
+    // must lie on the opposite side of the prefix
+    for (i, sibling) in siblings.iter().rev().enumerate() {
+        match (prefix[i], sibling.get_direction()) {
+            (true, Direction::Left) | (false, Direction::Right) => (),
+            _ => return Err(()),
+        }
+    }
+
+    let mut paths = HashSet::<[u8; 32]>::new();
+    let mut leaves = Vec::<([u8; 32], Hash)>::new();
+    for leaf in range_proof.get_leaves() {
+        let path = leaf.get_key_path();
+        let mut masked_path = path;
+        // colliding keys cannot be placed, hence cannot be proven
+        masked_path[31] &= 0xfe;
+        if !starts_with_prefix(&path, &prefix[..depth]) || !paths.insert(masked_path) {
+            return Err(());
+        }
+        leaves.push((path, leaf.get_hash()));
+    }
+
+    let subtree_hash = get_subtree_hash(leaves, depth as u8);
+    Ok(fold_siblings(siblings, subtree_hash))
+}
+
+/// Returns the Hash of the subtree, at the given depth, holding the Leaves of the given
+/// paths and Hashes, shaped as NodeGeneric::from_leaves would shape it.
+fn get_subtree_hash(leaves: Vec<([u8; 32], Hash)>, index: u8) -> Hash {
+    if index > 0 && leaves.len() < 2 {
+        return leaves.first().map_or(Empty::get_hash(), |(_, leaf_hash)| *leaf_hash);
+    }
+    let (right, left): (Vec<_>, Vec<_>) = leaves
+        .into_iter()
+        .partition(|(path, _)| get_bit_direction(path, index));
+    Internal::<(), ()>::create_hash(
+        get_subtree_hash(left, index + 1),
+        get_subtree_hash(right, index + 1),
+    )
+}
+
+impl MerkleProof for Proof {
+    fn get_root_hash_from(&self, leaf_hash: Hash) -> Result<Hash, ()> {
+        Ok(fold_siblings(self.get_siblings(), leaf_hash))
+    }
+
+    fn follows_path(&self, key_path: &[u8; 32]) -> bool {
+        follows_path(self.get_siblings(), key_path)
+    }
+
+    fn get_hash_count(&self) -> usize {
+        self.get_siblings().len()
+    }
+}
+
+impl<K, V> OpenedProof<K, V>
+where
+    K: Serialize + KeyPath + Eq + Clone,
+    V: Serialize + Clone,
+{
+    /// Returns a Result which contains: the Hash of the root, computed according to the
+    /// OpenedProof from the Hash of the Leaf opened by it; Err(()) if its Siblings do not
+    /// follow the path of its key, or if the OpenedProof claims the Hash of a root which
+    /// differs from the computed one. The returned Hash proves
+    /// nothing until it is compared with a trusted root, as verify_against does.
+    pub fn verify(&self) -> Result<Hash, ()> {
+        let (key, value) = (self.get_key().clone(), self.get_value().clone());
+        let my_leaf = match self.get_salt() {
+            Some(salt) => Leaf::new_salted(key, value, *salt),
+            None => Leaf::new(key, value),
+        };
+        let root_hash = fold_path(self.get_siblings(), &self.get_key().key_path(), my_leaf.get_hash())?;
+        match self.get_claimed_root() {
+            Some(root) if *root != root_hash => Err(()),
+            _ => Ok(root_hash),
+        }
+    }
+
+    /// Returns true if the OpenedProof proves its Leaf against the given trusted root.
+    pub fn verify_against(&self, trusted_root: Hash) -> bool {
+        self.verify() == Ok(trusted_root)
+    }
+}
+
+/// Returns true if every one of the given Siblings, from the deepest one to the one of the
+/// root, lies on the opposite side of the given path at its depth: the deepest one is at
+/// depth siblings.len() - 1.
+fn follows_path(siblings: &[Sibling], key_path: &[u8; 32]) -> bool {
+    siblings.len() <= 256
+        && siblings.iter().rev().enumerate().all(|(index, sibling)| {
+            matches!(
+                (get_bit_direction(key_path, index as u8), sibling.get_direction()),
+                (true, Direction::Left) | (false, Direction::Right)
+            )
+        })
+}
+
+/// Returns a Result which contains: the Hash of the root, computed as fold_siblings does;
+/// Err(()) if the given Siblings do not follow the given path.
+fn fold_path(siblings: &[Sibling], key_path: &[u8; 32], leaf_hash: Hash) -> Result<Hash, ()> {
+    if !follows_path(siblings, key_path) {
+        return Err(());
+    }
+    Ok(fold_siblings(siblings, leaf_hash))
+}
 
-    let mut hash_final = my_leaf.get_hash();
+/// Returns the Hash of the root, computed by hashing the given Hash of a Leaf 
+/// together with the given Siblings, from the deepest one to the one of the root.
+fn fold_siblings(siblings: &[Sibling], leaf_hash: Hash) -> Hash {
+    let mut hash_final = leaf_hash;
 
     for sibling in siblings {
         match sibling.get_direction() {
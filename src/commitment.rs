@@ -0,0 +1,234 @@
+use crate::mpt::*;
+use crate::structs::*;
+use crate::util::*;
+use serde::ser::{self, Impossible, Serializer};
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::fmt;
+use talk::crypto::primitives::hash::{hash, Hash};
+
+/*
+ * A MerkleTree whose Leaves only store the Hash of their value, so that the payloads
+ * can live elsewhere. The Hash of every Leaf, hence every Proof, is the same as the one
+ * of a MerkleTree storing the values themselves: clients verify their Proofs through
+ * client_verify::get_root_hash, hashing their own data.
+ *
+ * The Hash of a value is stored as a ValueHash, which the Leaves take as the Hash of their
+ * value instead of hashing it again, on every path recomputing the Hash of a Leaf.
+*/
+
+// the name a ValueHash serializes under, which tells it apart from any other value
+const VALUE_HASH: &str = "$commitment::ValueHash";
+
+/// The Hash of a value, stored in a Leaf in place of the value itself.
+#[derive(Deserialize, Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub struct ValueHash(pub Hash);
+
+impl Serialize for ValueHash {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(VALUE_HASH, &self.0)
+    }
+}
+
+/// Returns the Hash of the given value, or the Hash it carries if it is a ValueHash.
+pub fn hash_value<V>(value: &V) -> Hash
+where
+    V: Serialize + ?Sized,
+{
+    match value.serialize(ValueHashProbe) {
+        Ok(value_hash) => value_hash,
+        Err(NotValueHash) => hash(value).unwrap(),
+    }
+}
+
+#[derive(Debug)]
+struct NotValueHash;
+
+impl fmt::Display for NotValueHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "not a ValueHash")
+    }
+}
+
+impl std::error::Error for NotValueHash {}
+
+impl ser::Error for NotValueHash {
+    fn custom<T: fmt::Display>(_: T) -> Self {
+        NotValueHash
+    }
+}
+
+/// A Serializer which only succeeds on a ValueHash, returning the Hash it carries.
+/// Any other value is rejected by the first call it makes.
+struct ValueHashProbe;
+
+macro_rules! reject {
+    ($($method:ident($($arg:ty),*) -> $ok:ty;)*) => {
+        $(fn $method(self, $(_: $arg),*) -> Result<$ok, NotValueHash> {
+            Err(NotValueHash)
+        })*
+    };
+}
+
+impl Serializer for ValueHashProbe {
+    type Ok = Hash;
+    type Error = NotValueHash;
+    type SerializeSeq = Impossible<Hash, NotValueHash>;
+    type SerializeTuple = Impossible<Hash, NotValueHash>;
+    type SerializeTupleStruct = Impossible<Hash, NotValueHash>;
+    type SerializeTupleVariant = Impossible<Hash, NotValueHash>;
+    type SerializeMap = Impossible<Hash, NotValueHash>;
+    type SerializeStruct = Impossible<Hash, NotValueHash>;
+    type SerializeStructVariant = Impossible<Hash, NotValueHash>;
+
+    fn serialize_newtype_struct<T>(self, name: &'static str, value: &T) -> Result<Hash, NotValueHash>
+    where
+        T: Serialize + ?Sized,
+    {
+        if name != VALUE_HASH {
+            return Err(NotValueHash);
+        }
+        serde_json::to_value(value)
+            .and_then(serde_json::from_value)
+            .map_err(|_| NotValueHash)
+    }
+
+    fn serialize_some<T>(self, _: &T) -> Result<Hash, NotValueHash>
+    where
+        T: Serialize + ?Sized,
+    {
+        Err(NotValueHash)
+    }
+
+    fn serialize_newtype_variant<T>(self, _: &'static str, _: u32, _: &'static str, _: &T) -> Result<Hash, NotValueHash>
+    where
+        T: Serialize + ?Sized,
+    {
+        Err(NotValueHash)
+    }
+
+    reject! {
+        serialize_bool(bool) -> Hash;
+        serialize_i8(i8) -> Hash;
+        serialize_i16(i16) -> Hash;
+        serialize_i32(i32) -> Hash;
+        serialize_i64(i64) -> Hash;
+        serialize_u8(u8) -> Hash;
+        serialize_u16(u16) -> Hash;
+        serialize_u32(u32) -> Hash;
+        serialize_u64(u64) -> Hash;
+        serialize_f32(f32) -> Hash;
+        serialize_f64(f64) -> Hash;
+        serialize_char(char) -> Hash;
+        serialize_str(&str) -> Hash;
+        serialize_bytes(&[u8]) -> Hash;
+        serialize_none() -> Hash;
+        serialize_unit() -> Hash;
+        serialize_unit_struct(&'static str) -> Hash;
+        serialize_unit_variant(&'static str, u32, &'static str) -> Hash;
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+        serialize_struct(&'static str, usize) -> Self::SerializeStruct;
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+    }
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Hash)]
+pub struct CommittedMerkleTree<K>
+where
+    K: Serialize,
+{
+    tree: MerkleTree<K, ValueHash>,
+}
+
+impl<K> CommittedMerkleTree<K>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+{
+    /// Returns a new CommittedMerkleTree.
+    pub fn new() -> CommittedMerkleTree<K> {
+        CommittedMerkleTree {
+            tree: MerkleTree::new(),
+        }
+    }
+
+    /// Returns the underlying MerkleTree, whose values are the Hashes of the committed values.
+    pub fn get_tree(&self) -> &MerkleTree<K, ValueHash> {
+        &self.tree
+    }
+
+    /// Returns the Hash of the value previously associated to the given key, or None if the 
+    /// key was not contained. Hashes the given value and inserts a Leaf committing to it, or 
+    /// substitutes the commitment associated to the given key.
+    /// Panics if there is a collision
+    pub fn insert<T>(&mut self, key_to_add: K, value_to_add: &T) -> Option<Hash>
+    where
+        T: Serialize,
+    {
+        self.insert_value_hash(key_to_add, hash(value_to_add).unwrap())
+    }
+
+    /// Returns the Hash of the value previously associated to the given key, or None if the
+    /// key was not contained. Inserts a Leaf committing to the value whose Hash is given, 
+    /// or substitutes the commitment associated to the given key.
+    /// Panics if there is a collision
+    pub fn insert_value_hash(&mut self, key_to_add: K, value_hash: Hash) -> Option<Hash> {
+        self.tree
+            .insert(key_to_add, ValueHash(value_hash))
+            .map(|ValueHash(old)| old)
+    }
+
+    /// Returns a reference of the Hash of the value associated to the given key.
+    /// Panics if the given key is not associated to any value in the CommittedMerkleTree.
+    pub fn get_value_hash<Q>(&self, key: &Q) -> &Hash
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        &self.tree.get_value(key).0
+    }
+
+    /// Returns the hash of the root of the CommittedMerkleTree. Recursively computes
+    /// and assigns the corresponding Hash to every internal node.
+    pub fn compute_hashes(&mut self) -> Hash {
+        self.tree.compute_hashes()
+    }
+
+    /// Returns a Proof for the specific given key, computing the Hashes discarded since
+    /// the last call to compute_hashes. The Proof contains an empty vector of Siblings if
+    /// the key is not contained.
+    pub fn prove(&mut self, key: K) -> Proof {
+        self.tree.prove(key)
+    }
+
+    /// Returns an OpenedProof for the specific given key, carrying the Hash of its value
+    /// as a ValueHash, or None if the key is not contained, as MerkleTree::prove_opened does.
+    pub fn prove_opened<Q>(&mut self, key: &Q) -> Option<OpenedProof<K, ValueHash>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.tree.prove_opened(key)
+    }
+
+    /// Returns a RangeProof for every key whose path starts with the given prefix, carrying
+    /// the Hashes of their values as ValueHashes, as MerkleTree::prove_range does.
+    pub fn prove_range(&mut self, prefix_bits: &[bool]) -> Result<RangeProof<K, ValueHash>, RangeError> {
+        self.tree.prove_range(prefix_bits)
+    }
+}
+
+impl<K> Default for CommittedMerkleTree<K>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+{
+    fn default() -> Self {
+        CommittedMerkleTree::new()
+    }
+}
@@ -0,0 +1,284 @@
+use crate::arena::*;
+use crate::node_generic::*;
+use crate::util::*;
+use serde::Serialize;
+use std::ops::{Deref, DerefMut};
+use talk::crypto::primitives::hash::Hash;
+
+/*
+ * Entry API of the MerkleTree and of the ArenaMerkleTree, in the fashion of the one of HashMap. Every mutable access
+ * to a value goes through a ValueMut, which refreshes the Hash of the Leaf once dropped.
+ * The cached Hashes of the Internal nodes along the path are discarded only once a value
+ * is actually written or inserted, so that reading through an Entry keeps them.
+*/
+
+pub enum Entry<'a, K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+pub struct OccupiedEntry<'a, K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    leaf: &'a mut Leaf<K, V>,
+    path_hashes: Vec<&'a mut Option<Hash>>,
+}
+
+pub struct VacantEntry<'a, K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    key: K,
+    slot: VacantSlot<'a, K, V>,
+}
+
+/// Where the key of a VacantEntry is to be inserted.
+enum VacantSlot<'a, K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    /// Below a node of a MerkleTree, found at the given depth.
+    Node {
+        node: &'a mut NodeGeneric<K, V>,
+        index: u8,
+        len: &'a mut usize,
+        path_hashes: Vec<&'a mut Option<Hash>>,
+    },
+    /// In an ArenaMerkleTree.
+    Arena(&'a mut ArenaMerkleTree<K, V>),
+}
+
+pub struct ValueMut<'a, K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    leaf: &'a mut Leaf<K, V>,
+    path_hashes: Vec<&'a mut Option<Hash>>,
+    modified: bool,
+}
+
+impl<'a, K, V> Entry<'a, K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    /// Returns a mutable access to the value of the Entry, inserting the given
+    /// default value if the Entry is vacant.
+    /// Panics if there is a collision.
+    pub fn or_insert(self, default: V) -> ValueMut<'a, K, V> {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default),
+        }
+    }
+
+    /// Returns a mutable access to the value of the Entry, inserting the value returned
+    /// by the given function if the Entry is vacant.
+    /// Panics if there is a collision.
+    pub fn or_insert_with<F>(self, default: F) -> ValueMut<'a, K, V>
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default()),
+        }
+    }
+
+    /// Returns the Entry once the given function has modified its value, if the Entry is 
+    /// occupied. The Hash of the Leaf is refreshed right after the modification.
+    pub fn and_modify<F>(self, f: F) -> Self
+    where
+        F: FnOnce(&mut V),
+    {
+        match self {
+            Entry::Occupied(mut e) => {
+                f(&mut *e.get_mut());
+                Entry::Occupied(e)
+            }
+            Entry::Vacant(e) => Entry::Vacant(e),
+        }
+    }
+
+    /// Returns a reference to the key of the Entry.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(e) => e.key(),
+            Entry::Vacant(e) => e.key(),
+        }
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    /// Returns a new OccupiedEntry, given the cached Hashes of the Internal nodes
+    /// along the path leading to its Leaf.
+    pub fn new(leaf: &'a mut Leaf<K, V>, path_hashes: Vec<&'a mut Option<Hash>>) -> Self {
+        OccupiedEntry { leaf, path_hashes }
+    }
+
+    /// Returns a reference to the key of the OccupiedEntry.
+    pub fn key(&self) -> &K {
+        self.leaf.get_key()
+    }
+
+    /// Returns a reference to the value of the OccupiedEntry.
+    pub fn get(&self) -> &V {
+        self.leaf.get_value()
+    }
+
+    /// Returns a mutable access to the value of the OccupiedEntry.
+    pub fn get_mut(&mut self) -> ValueMut<'_, K, V> {
+        let path_hashes = self.path_hashes.iter_mut().map(|h| &mut **h).collect();
+        ValueMut::new(self.leaf, path_hashes)
+    }
+
+    /// Returns a mutable access to the value of the OccupiedEntry, bound to the MerkleTree.
+    pub fn into_mut(self) -> ValueMut<'a, K, V> {
+        ValueMut::new(self.leaf, self.path_hashes)
+    }
+
+    /// Returns the previous value of the OccupiedEntry, substituted by the given one.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(&mut *self.get_mut(), value)
+    }
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    /// Returns a new VacantEntry, whose key is to be inserted below the given node, 
+    /// found at the given depth, given the number of entries of the MerkleTree and the
+    /// cached Hashes of the Internal nodes along the path leading to the node.
+    pub fn new(
+        key: K,
+        node: &'a mut NodeGeneric<K, V>,
+        index: u8,
+        len: &'a mut usize,
+        path_hashes: Vec<&'a mut Option<Hash>>,
+    ) -> Self {
+        VacantEntry {
+            key,
+            slot: VacantSlot::Node {
+                node,
+                index,
+                len,
+                path_hashes,
+            },
+        }
+    }
+
+    /// Returns a new VacantEntry, whose key is to be inserted in the given ArenaMerkleTree.
+    pub fn from_arena(key: K, arena: &'a mut ArenaMerkleTree<K, V>) -> Self {
+        VacantEntry {
+            key,
+            slot: VacantSlot::Arena(arena),
+        }
+    }
+
+    /// Returns a reference to the key of the VacantEntry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Returns the key of the VacantEntry.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Returns a mutable access to the given value, once inserted in the MerkleTree
+    /// together with the key of the VacantEntry.
+    /// Panics if there is a collision.
+    pub fn insert(self, value: V) -> ValueMut<'a, K, V> {
+        let key = self.key.clone();
+        match self.slot {
+            VacantSlot::Node {
+                node,
+                index,
+                len,
+                path_hashes,
+            } => {
+                node.insert(self.key, value, index);
+                *len += 1;
+                for h in path_hashes {
+                    *h = None;
+                }
+                // the Internal nodes below the node, if any, have just been created without a Hash
+                let (node, _, path_hashes) = node.find_path_mut(&key, index);
+                ValueMut::new(node.get_mut_leaf(), path_hashes)
+            }
+            VacantSlot::Arena(arena) => {
+                arena.insert(self.key, value);
+                arena.get_mut(&key).unwrap()
+            }
+        }
+    }
+}
+
+impl<'a, K, V> ValueMut<'a, K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    /// Returns a new ValueMut, giving access to the value of the given Leaf. The given cached
+    /// Hashes of the Internal nodes along the path are discarded if the value is modified.
+    pub fn new(leaf: &'a mut Leaf<K, V>, path_hashes: Vec<&'a mut Option<Hash>>) -> Self {
+        ValueMut {
+            leaf,
+            path_hashes,
+            modified: false,
+        }
+    }
+}
+
+impl<'a, K, V> Deref for ValueMut<'a, K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        self.leaf.get_value()
+    }
+}
+
+impl<'a, K, V> DerefMut for ValueMut<'a, K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    fn deref_mut(&mut self) -> &mut V {
+        self.modified = true;
+        self.leaf.get_mut_value()
+    }
+}
+
+impl<'a, K, V> Drop for ValueMut<'a, K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    fn drop(&mut self) {
+        if self.modified {
+            self.leaf.refresh_hash();
+            for h in self.path_hashes.iter_mut() {
+                **h = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use crate::node_generic::*;
+use crate::structs::*;
+use crate::util::*;
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use talk::crypto::primitives::hash::{hash, Hash};
+
+/*
+ * Radix-16 variant of the Merkle Patricia Tree. Every Branch node has 16 children and
+ * each level walks 4 bits of the path of a key, so that a key is found at depth 64 at
+ * most instead of 256. The Leaves and the Empty nodes are the ones of the binary tree,
+ * and the shape is canonical as well: every Leaf sits at the smallest depth at which
+ * its path is told apart from every other one, and every Branch other than the root
+ * holds at least two Leaves.
+*/
+
+const BITS_PER_LEVEL: u8 = 4;
+const RADIX: usize = 1 << BITS_PER_LEVEL;
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub enum HexaryNode<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    Branch(HexaryBranch<K, V>),
+    Leaf(Leaf<K, V>),
+    Empty(Empty),
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct HexaryBranch<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    children: Vec<HexaryNode<K, V>>,
+    my_hash: Option<Hash>,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct HexaryMerkleTree<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    root: HexaryNode<K, V>,
+    len: usize,
+}
+
+/// One level of a HexaryProof: the position of the proven path among the 16 children
+/// of a Branch, together with the Hashes of the 15 other children, in order.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct HexaryLevel {
+    position: u8,
+    siblings: Vec<Hash>,
+}
+
+/// A Proof issued by a HexaryMerkleTree. The levels go from the deepest one to the one of the root.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct HexaryProof {
+    levels: Vec<HexaryLevel>,
+}
+
+impl<K, V> HexaryMerkleTree<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    /// Returns a new HexaryMerkleTree.
+    pub fn new() -> HexaryMerkleTree<K, V> {
+        HexaryMerkleTree {
+            root: HexaryNode::Branch(HexaryBranch::new()),
+            len: 0,
+        }
+    }
+
+    /// Returns the root of the HexaryMerkleTree.
+    pub fn get_root(&self) -> &HexaryNode<K, V> {
+        &self.root
+    }
+
+    /// Returns the value previously associated to the given key, or None if the key was
+    /// not contained. Inserts a new Leaf in the HexaryMerkleTree if the key is not
+    /// contained or substitutes the current value associated to the given key.
+    /// Panics if there is a collision
+    pub fn insert(&mut self, key_to_add: K, value_to_add: V) -> Option<V> {
+        match self.try_insert(key_to_add, value_to_add) {
+            Ok(old) => old,
+            Err(Collision) => {
+                panic!("followed the same path: different keys but same hash ---> Collision")
+            }
+        }
+    }
+
+    /// Returns a Result which contains: the value previously associated to the given key, or
+    /// None if the key was not contained; Err(Collision), leaving the HexaryMerkleTree untouched,
+    /// if the given key collides with another key. Behaves as insert otherwise.
+    pub fn try_insert(&mut self, key_to_add: K, value_to_add: V) -> Result<Option<V>, Collision> {
+        let key_path = key_to_add.key_path();
+        let old = self
+            .root
+            .try_insert_leaf(Leaf::new(key_to_add, value_to_add), &key_path, 0)?;
+        if old.is_none() {
+            self.len += 1;
+        }
+        Ok(old.map(|old| old.get_value().clone()))
+    }
+
+    /// Returns a reference of the value associated to the given key,
+    /// or None if the key is not contained.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        let key_path = key.key_path();
+        let mut node = &self.root;
+        let mut index = 0;
+        loop {
+            match node {
+                HexaryNode::Branch(n) => {
+                    node = &n.children[get_digit(&key_path, index, BITS_PER_LEVEL)];
+                    index += 1;
+                }
+                HexaryNode::Leaf(n) if n.get_key().borrow() == key => return Some(n.get_value()),
+                _ => return None,
+            }
+        }
+    }
+
+    /// Returns the number of entries of the HexaryMerkleTree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the HexaryMerkleTree holds no entry.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the hash of the root of the HexaryMerkleTree. Computes and assigns the
+    /// corresponding Hash to every Branch node modified since the last call: the other Hashes are kept.
+    pub fn compute_hashes(&mut self) -> Hash {
+        self.root.compute_hashes()
+    }
+
+    /// Returns true if the HexaryMerkleTree has been modified since the last call to compute_hashes,
+    /// so that some Hashes are to be computed again before try_prove can generate a HexaryProof.
+    pub fn has_stale_hashes(&self) -> bool {
+        self.root.get_cached_hash().is_err()
+    }
+
+    /// Returns a HexaryProof for the specific given key. Only the Hashes discarded since the
+    /// last call to compute_hashes are computed again, as compute_hashes does.
+    /// The HexaryProof contains an empty vector of levels if the key is not contained.
+    pub fn prove<Q>(&mut self, key: &Q) -> HexaryProof
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.compute_hashes();
+        match self.try_prove(key) {
+            Ok(proof) => proof,
+            Err(StaleHashes) => unreachable!("the Hashes have just been computed"),
+        }
+    }
+
+    /// Returns a Result which contains: a HexaryProof for the specific given key, as prove does;
+    /// Err(StaleHashes) if the Hash of a Branch node needed by the HexaryProof has been discarded
+    /// by a modification since the last call to compute_hashes.
+    pub fn try_prove<Q>(&self, key: &Q) -> Result<HexaryProof, StaleHashes>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        let key_path = key.key_path();
+        let mut levels = Vec::<HexaryLevel>::new();
+        let mut node = &self.root;
+        let mut index = 0;
+        loop {
+            match node {
+                HexaryNode::Branch(n) => {
+                    let position = get_digit(&key_path, index, BITS_PER_LEVEL);
+                    let siblings = n
+                        .children
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| *i != position)
+                        .map(|(_, child)| child.get_cached_hash())
+                        .collect::<Result<Vec<Hash>, StaleHashes>>()?;
+                    levels.push(HexaryLevel::new(position as u8, siblings));
+                    node = &n.children[position];
+                    index += 1;
+                }
+                HexaryNode::Leaf(n) if n.get_key().borrow() == key => break,
+                _ => return Ok(HexaryProof::new(Vec::new())),
+            }
+        }
+        levels.reverse();
+        Ok(HexaryProof::new(levels))
+    }
+}
+
+impl<K, V> Default for HexaryMerkleTree<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    fn default() -> Self {
+        HexaryMerkleTree::new()
+    }
+}
+
+impl<K, V> HexaryNode<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    /// Returns a Result which contains: the Leaf previously associated to the same key, or
+    /// None if the key was not contained; Err(Collision) if the key of the given Leaf, whose
+    /// path is given, collides with another key. The cached Hashes along the path are discarded.
+    fn try_insert_leaf(
+        &mut self,
+        leaf_to_add: Leaf<K, V>,
+        key_path: &[u8; 32],
+        index: u8,
+    ) -> Result<Option<Leaf<K, V>>, Collision> {
+        match self {
+            HexaryNode::Branch(n) => {
+                n.my_hash = None;
+                let position = get_digit(key_path, index, BITS_PER_LEVEL);
+                n.children[position].try_insert_leaf(leaf_to_add, key_path, index + 1)
+            }
+            HexaryNode::Leaf(n) if n.get_key() == leaf_to_add.get_key() => {
+                Ok(Some(std::mem::replace(n, leaf_to_add)))
+            }
+            HexaryNode::Leaf(n) => {
+                let old_key_path = n.get_key().key_path();
+                if &old_key_path == key_path {
+                    return Err(Collision);
+                }
+                // this Leaf holds another key, so move it one level deeper below a new Branch
+                let old_leaf = match std::mem::replace(self, HexaryNode::Empty(Empty::new())) {
+                    HexaryNode::Leaf(old_leaf) => old_leaf,
+                    _ => panic!("Node which is not a Leaf!"),
+                };
+                let mut branch = HexaryBranch::new();
+                branch.children[get_digit(&old_key_path, index, BITS_PER_LEVEL)] = HexaryNode::Leaf(old_leaf);
+                let position = get_digit(key_path, index, BITS_PER_LEVEL);
+                let replaced = branch.children[position].try_insert_leaf(leaf_to_add, key_path, index + 1);
+                *self = HexaryNode::Branch(branch);
+                replaced
+            }
+            HexaryNode::Empty(_) => {
+                *self = HexaryNode::Leaf(leaf_to_add);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns the Hash of the node. Recursively computes and assigns the corresponding
+    /// Hash to every Branch node of its subtree whose cached Hash was discarded.
+    fn compute_hashes(&mut self) -> Hash {
+        match self {
+            HexaryNode::Branch(HexaryBranch { my_hash: Some(h), .. }) => *h,
+            HexaryNode::Branch(n) => {
+                let children_hashes: Vec<Hash> = n.children.iter_mut().map(|child| child.compute_hashes()).collect();
+                let this_hash = HexaryBranch::<K, V>::create_hash(&children_hashes);
+                n.my_hash = Some(this_hash);
+                this_hash
+            }
+            HexaryNode::Leaf(n) => n.get_hash(),
+            HexaryNode::Empty(_) => Empty::get_hash(),
+        }
+    }
+
+    /// Returns the cached Hash of the node, or Err(StaleHashes)
+    /// if the Hash of a Branch node has not been computed.
+    pub fn get_cached_hash(&self) -> Result<Hash, StaleHashes> {
+        match self {
+            HexaryNode::Branch(n) => n.my_hash.ok_or(StaleHashes),
+            HexaryNode::Leaf(n) => Ok(n.get_hash()),
+            HexaryNode::Empty(_) => Ok(Empty::get_hash()),
+        }
+    }
+}
+
+impl<K, V> HexaryBranch<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    /// Returns a new HexaryBranch whose 16 children are Empty.
+    pub fn new() -> Self {
+        HexaryBranch {
+            children: (0..RADIX).map(|_| HexaryNode::Empty(Empty::new())).collect(),
+            my_hash: None,
+        }
+    }
+
+    /// Returns a reference to the 16 children of the Branch node.
+    pub fn get_children(&self) -> &Vec<HexaryNode<K, V>> {
+        &self.children
+    }
+}
+
+impl<K, V> Default for HexaryBranch<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    fn default() -> Self {
+        HexaryBranch::new()
+    }
+}
+
+impl<K, V> HexaryBranch<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    /// Returns the Hash of a Branch node, given the Hashes of its 16 children in order.
+    pub fn create_hash(children_hashes: &Vec<Hash>) -> Hash {
+        hash(children_hashes).unwrap()
+    }
+}
+
+impl HexaryLevel {
+    /// Returns a new HexaryLevel.
+    pub fn new(position: u8, siblings: Vec<Hash>) -> Self {
+        HexaryLevel { position, siblings }
+    }
+
+    /// Returns the position of the proven path among the children of the Branch.
+    pub fn get_position(&self) -> u8 {
+        self.position
+    }
+
+    /// Returns a reference to the Hashes of the other children of the Branch.
+    pub fn get_siblings(&self) -> &Vec<Hash> {
+        &self.siblings
+    }
+}
+
+impl HexaryProof {
+    /// Returns a new HexaryProof.
+    pub fn new(levels: Vec<HexaryLevel>) -> Self {
+        HexaryProof { levels }
+    }
+
+    /// Returns a reference to the levels of the HexaryProof, from the deepest one to the one of the root.
+    pub fn get_levels(&self) -> &Vec<HexaryLevel> {
+        &self.levels
+    }
+}
+
+impl MerkleProof for HexaryProof {
+    fn get_root_hash_from(&self, leaf_hash: Hash) -> Result<Hash, ()> {
+        let mut hash_final = leaf_hash;
+
+        for level in self.levels.iter() {
+            if level.siblings.len() != RADIX - 1 || level.position as usize >= RADIX {
+                return Err(());
+            }
+            let mut children_hashes = level.siblings.clone();
+            children_hashes.insert(level.position as usize, hash_final);
+            hash_final = HexaryBranch::<(), ()>::create_hash(&children_hashes);
+        }
+        Ok(hash_final)
+    }
+
+    fn follows_path(&self, key_path: &[u8; 32]) -> bool {
+        self.levels.len() <= 256 / BITS_PER_LEVEL as usize
+            && self.levels.iter().rev().enumerate().all(|(index, level)| {
+                level.position as usize == get_digit(key_path, index as u8, BITS_PER_LEVEL)
+            })
+    }
+
+    fn get_hash_count(&self) -> usize {
+        self.levels.len() * (RADIX - 1)
+    }
+}
@@ -0,0 +1,200 @@
+use crate::mpt::*;
+use crate::util::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use talk::crypto::primitives::hash::{hash, Hash};
+
+/*
+ * Write-ahead journal of the mutations of a MerkleTree. A JournaledMerkleTree keeps its
+ * files in a directory: the last snapshot of the MerkleTree, as JSON, and the journal of
+ * every insert and remove since then. Each record of the journal is appended before the
+ * MerkleTree is modified, and is laid out as
+ *     length of the payload (4 bytes, little endian) | Hash of the payload | payload (JSON)
+ * On opening, the journal is replayed onto the snapshot. A record which is cut short or
+ * whose Hash does not match is the trace of a crash in the middle of a write: the journal
+ * is truncated right before it, together with whatever follows. A write which fails while
+ * the journal is open cuts the journal back to its last complete record as well, so that
+ * the next record never follows a torn one.
+*/
+
+pub const SNAPSHOT_FILE: &str = "snapshot.json";
+pub const JOURNAL_FILE: &str = "journal.log";
+
+const HEADER_SIZE: usize = 4 + 32;
+
+/// A mutation of a MerkleTree, as recorded by a journal.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum JournalOp<K, V> {
+    Insert { key: K, value: V },
+    Remove { key: K },
+}
+
+pub struct JournaledMerkleTree<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    tree: MerkleTree<K, V>,
+    journal: File,
+    /// The length of the journal up to the end of its last complete record.
+    journal_len: u64,
+    dir: PathBuf,
+}
+
+impl<K, V> JournaledMerkleTree<K, V>
+where
+    K: Serialize + DeserializeOwned + KeyPath + Clone + Eq,
+    V: Serialize + DeserializeOwned + Clone,
+{
+    /// Returns the JournaledMerkleTree kept in the given directory, which is created if needed.
+    /// The journal is replayed onto the last snapshot, or onto an empty MerkleTree if no
+    /// snapshot has been taken yet, and the hashes of the MerkleTree are computed.
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut tree = match fs::read(dir.join(SNAPSHOT_FILE)) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => MerkleTree::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut journal = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dir.join(JOURNAL_FILE))?;
+        let mut bytes = Vec::<u8>::new();
+        journal.read_to_end(&mut bytes)?;
+
+        let (ops, valid_len) = read_records::<K, V>(&bytes);
+        if valid_len < bytes.len() {
+            journal.set_len(valid_len as u64)?;
+        }
+        for op in ops {
+            match op {
+                JournalOp::Insert { key, value } => {
+                    // a colliding key left the MerkleTree untouched the first time as well
+                    let _ = tree.try_insert(key, value);
+                }
+                JournalOp::Remove { key } => {
+                    tree.remove(&key);
+                }
+            }
+        }
+        tree.compute_hashes();
+
+        Ok(JournaledMerkleTree {
+            tree,
+            journal,
+            journal_len: valid_len as u64,
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Returns a reference to the MerkleTree.
+    pub fn get_tree(&self) -> &MerkleTree<K, V> {
+        &self.tree
+    }
+
+    /// Returns the value previously associated to the given key, or None if the key was not
+    /// contained, once the insert has been recorded by the journal. Behaves as MerkleTree::insert.
+    /// Panics if there is a collision
+    pub fn insert(&mut self, key_to_add: K, value_to_add: V) -> io::Result<Option<V>> {
+        self.append(&JournalOp::Insert {
+            key: key_to_add.clone(),
+            value: value_to_add.clone(),
+        })?;
+        Ok(self.tree.insert(key_to_add, value_to_add))
+    }
+
+    /// Returns the value associated to the given key, or None if the key was not contained,
+    /// once the remove has been recorded by the journal. Behaves as MerkleTree::remove.
+    pub fn remove(&mut self, key: &K) -> io::Result<Option<V>> {
+        self.append(&JournalOp::Remove { key: key.clone() })?;
+        Ok(self.tree.remove(key))
+    }
+
+    /// Returns the hash of the root of the MerkleTree, as MerkleTree::compute_hashes does.
+    pub fn compute_hashes(&mut self) -> Hash {
+        self.tree.compute_hashes()
+    }
+
+    /// Returns the hash of the root of the MerkleTree, once a snapshot of the MerkleTree
+    /// has replaced the previous one and the journal has been emptied.
+    pub fn snapshot(&mut self) -> io::Result<Hash> {
+        let root_hash = self.tree.compute_hashes();
+        let bytes = serde_json::to_vec(&self.tree).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        // the snapshot is replaced at once: a crash before the journal is emptied only
+        // replays mutations the snapshot already holds, to the same result
+        let tmp_path = self.dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&bytes)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE))?;
+
+        self.journal.set_len(0)?;
+        self.journal_len = 0;
+        self.journal.sync_all()?;
+        Ok(root_hash)
+    }
+
+    /// Flushes the journal to the disk, so that the recorded mutations outlive a crash
+    /// of the machine and not only of the process.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.journal.sync_data()
+    }
+
+    /// Appends the given mutation to the journal as a single record. The bytes of a torn
+    /// record, left by a failed write, are removed first; if the write fails, the journal is
+    /// cut back to its last complete record.
+    fn append(&mut self, op: &JournalOp<K, V>) -> io::Result<()> {
+        let payload = serde_json::to_vec(op).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut record = Vec::<u8>::with_capacity(HEADER_SIZE + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&hash(&payload).unwrap().to_bytes());
+        record.extend_from_slice(&payload);
+
+        if self.journal.metadata()?.len() != self.journal_len {
+            self.journal.set_len(self.journal_len)?;
+        }
+        if let Err(e) = self.journal.write_all(&record) {
+            // the journal is left torn if even this fails, and cut back by the next append
+            let _ = self.journal.set_len(self.journal_len);
+            return Err(e);
+        }
+        self.journal_len += record.len() as u64;
+        Ok(())
+    }
+}
+
+/// Returns the mutations recorded by the given bytes of a journal, together with the number
+/// of bytes they span. The bytes following the first incomplete or corrupted record are ignored.
+fn read_records<K, V>(bytes: &[u8]) -> (Vec<JournalOp<K, V>>, usize)
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+{
+    let mut ops = Vec::<JournalOp<K, V>>::new();
+    let mut offset = 0;
+    while bytes.len() - offset >= HEADER_SIZE {
+        let mut len_bytes = [0u8; 4];
+        len_bytes.copy_from_slice(&bytes[offset..offset + 4]);
+        let mut checksum = [0u8; 32];
+        checksum.copy_from_slice(&bytes[offset + 4..offset + HEADER_SIZE]);
+
+        let start = offset + HEADER_SIZE;
+        let end = start + u32::from_le_bytes(len_bytes) as usize;
+        if end > bytes.len() || hash(&bytes[start..end]).unwrap().to_bytes() != checksum {
+            break;
+        }
+        match serde_json::from_slice(&bytes[start..end]) {
+            Ok(op) => ops.push(op),
+            Err(_) => break,
+        }
+        offset = end;
+    }
+    (ops, offset)
+}
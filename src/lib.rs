@@ -0,0 +1,21 @@
+// the trees report their failures as Result<_, ()>, as the original lookups do
+#![allow(clippy::result_unit_err)]
+
+#[cfg(test)]
+mod test;
+pub mod accumulator;
+pub mod anti_entropy;
+pub mod arena;
+pub mod bench;
+pub mod client_verify;
+pub mod commitment;
+pub mod entry;
+pub mod hexary;
+pub mod journal;
+pub mod mpt;
+pub mod node_generic;
+pub mod root_log;
+pub mod shared;
+pub mod smt;
+pub mod structs;
+pub mod util;
@@ -1,10 +1,15 @@
+use first_rust_project::bench;
 
-mod MPT;
-mod test;
-mod client_verify;
-mod mpt;
-mod node_generic;
-mod structs;
-mod util;
-
-fn main() {}
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("bench") {
+        let max_keys = match args.get(2).map_or(Ok(1_000_000), |keys| keys.parse()) {
+            Ok(max_keys) => max_keys,
+            Err(_) => {
+                eprintln!("usage: {} bench [max_keys]", args[0]);
+                std::process::exit(2);
+            }
+        };
+        bench::run(max_keys);
+    }
+}
@@ -1,33 +1,110 @@
+use crate::entry::*;
 use crate::util::*;
 use crate::node_generic::*;
 use crate::structs::*;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::borrow::Borrow;
+use std::collections::{hash_map, HashMap, HashSet};
 use talk::crypto::primitives::hash::Hash;
 
 /**
  * In this representation of the Merkle Patricia Tree,
  * • true <--> 1 <--> Right
  * • false <--> 0 <--> Left
+ *
+ * Every Leaf sits at the smallest depth at which the path of its key tells it apart
+ * from every other key. The shape of a MerkleTree, hence its root Hash, only depends
+ * on the entries it holds, not on the order in which they were inserted.
 **/
 
-#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Hash)]
+#[derive(Serialize, Debug, Clone)]
 pub struct MerkleTree<K, V>
 where
     K: Serialize,
     V: Serialize,
 {
     root: Box<NodeGeneric<K, V>>,
+    // derived from the Leaves: neither serialized nor compared
+    #[serde(skip)]
+    len: usize,
+}
+
+impl<K, V> PartialEq for MerkleTree<K, V>
+where
+    K: Serialize + PartialEq,
+    V: Serialize + PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.root == other.root
+    }
+}
+
+impl<K, V> Eq for MerkleTree<K, V>
+where
+    K: Serialize + Eq,
+    V: Serialize + Eq,
+{
+}
+
+impl<K, V> std::hash::Hash for MerkleTree<K, V>
+where
+    K: Serialize + std::hash::Hash,
+    V: Serialize + std::hash::Hash,
+{
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.root.hash(state);
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for MerkleTree<K, V>
+where
+    K: Serialize + Deserialize<'de>,
+    V: Serialize + Deserialize<'de>,
+{
+    /// Deserializes the root of the MerkleTree as it was serialized, cached Hashes included,
+    /// and counts its Leaves again.
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Fields<K, V>
+        where
+            K: Serialize,
+            V: Serialize,
+        {
+            root: Box<NodeGeneric<K, V>>,
+        }
+
+        let fields = Fields::<K, V>::deserialize(deserializer)?;
+        let len = fields.root.count_leaves();
+        Ok(MerkleTree {
+            root: fields.root,
+            len,
+        })
+    }
 }
 
 impl<K, V> MerkleTree<K, V>
 where
-    K: Serialize + Clone + Eq,
+    K: Serialize + KeyPath + Clone + Eq,
     V: Serialize + Clone,
 {
     /// Returns a new MerkleTree
     pub fn new() -> MerkleTree<K, V> {
         let n = Box::new(NodeGeneric::new_internal_default());
-        MerkleTree { root: n }
+        MerkleTree { root: n, len: 0 }
+    }
+
+    /// Returns a new MerkleTree holding every Leaf of the given node, which may have been 
+    /// built by hand through Internal::new. The MerkleTree is rebuilt in its canonical shape.
+    pub fn from_root(root: NodeGeneric<K, V>) -> MerkleTree<K, V> {
+        let mut mpt = MerkleTree {
+            root: Box::new(root),
+            len: 0,
+        };
+        mpt.canonicalize();
+        mpt
     }
 
     /// Returns the root of the MerkleTree as a NodeGeneric.
@@ -36,67 +113,401 @@ where
     }
 
     /// Returns the mutable root of the MerkleTree as NodeGeneric.
+    /// Neither the number of entries nor the cached Hashes are tracked through the returned
+    /// reference: canonicalize is to be invoked after adding or removing Leaves by hand.
     pub fn get_mut_root(&mut self) -> &mut NodeGeneric<K, V> {
         &mut self.root
     }
 
-    /// Returns the created Leaf node as NodeGeneric. 
-    /// Returns an already existing Leaf as NodeGeneric if the key to be inserted
-    /// already exists. Inserts a new Leaf in the MerkleTree if the key is not 
+    /// Returns the value previously associated to the given key, or None if the key was 
+    /// not contained. Inserts a new Leaf in the MerkleTree if the key is not 
     /// contained or substitutes the current value associated to the given key.
     /// Panics if there is a collision
-    pub fn insert(&mut self, key_to_add: K, value_to_add: V) -> NodeGeneric<K, V> {
-        self.root.insert(key_to_add, value_to_add, 0)
+    pub fn insert(&mut self, key_to_add: K, value_to_add: V) -> Option<V> {
+        let old = self.root.insert(key_to_add, value_to_add, 0);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    /// Returns the value previously associated to the given key, or None if the key was 
+    /// not contained. Inserts a new Leaf salted with the given salt in the MerkleTree if the
+    /// key is not contained or substitutes the current Leaf associated to the given key. 
+    /// The salt is to be revealed to the owner of the key only, together with its Proof.
+    /// Panics if there is a collision
+    pub fn insert_salted(&mut self, key_to_add: K, value_to_add: V, salt: Hash) -> Option<V> {
+        self.insert_leaf(Leaf::new_salted(key_to_add, value_to_add, salt))
+            .map(|old| old.get_value().clone())
+    }
+
+    /// Returns the Leaf previously associated to the same key, or None if the key was 
+    /// not contained. Inserts the given Leaf in the MerkleTree if its key is not contained
+    /// or substitutes the Leaf associated to the same key, Hash included.
+    /// Panics if there is a collision
+    pub fn insert_leaf(&mut self, leaf_to_add: Leaf<K, V>) -> Option<Leaf<K, V>> {
+        let old = self.root.insert_leaf(leaf_to_add, 0);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    /// Returns a Result which contains: the value previously associated to the given key, or 
+    /// None if the key was not contained; Err(Collision), leaving the MerkleTree untouched, if
+    /// the given key collides with another key. Behaves as insert otherwise.
+    pub fn try_insert(&mut self, key_to_add: K, value_to_add: V) -> Result<Option<V>, Collision> {
+        self.try_insert_leaf(Leaf::new(key_to_add, value_to_add))
+            .map(|old| old.map(|old| old.get_value().clone()))
+    }
+
+    /// Returns a Result which contains: the Leaf previously associated to the same key, or 
+    /// None if the key was not contained; Err(Collision), leaving the MerkleTree untouched, if
+    /// the key of the given Leaf collides with another key. Behaves as insert_leaf otherwise.
+    pub fn try_insert_leaf(&mut self, leaf_to_add: Leaf<K, V>) -> Result<Option<Leaf<K, V>>, Collision> {
+        let old = self.root.try_insert_leaf(leaf_to_add, 0)?;
+        if old.is_none() {
+            self.len += 1;
+        }
+        Ok(old)
+    }
+
+    /// Returns the value associated to the given key, or None if the key is not contained.
+    /// Removes the Leaf of the key from the MerkleTree, which is kept in its canonical shape.
+    /// The cached Hashes along the path are discarded.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        let removed = self.root.remove(key, 0)?;
+        self.len -= 1;
+        Some(removed.get_value().clone())
+    }
+
+    /// Returns the Entry of the given key, to be read, inserted or modified in place
+    /// with a single traversal. The cached Hashes along the path are discarded only
+    /// once a value is inserted or modified through the Entry.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        let (node, index, path_hashes) = self.root.find_path_mut(&key, 0);
+        let occupied = match node {
+            NodeGeneric::Leaf(n) => n.get_key() == &key,
+            _ => false,
+        };
+        if occupied {
+            Entry::Occupied(OccupiedEntry::new(node.get_mut_leaf(), path_hashes))
+        } else {
+            Entry::Vacant(VacantEntry::new(key, node, index, &mut self.len, path_hashes))
+        }
+    }
+
+    /// Returns a mutable access to the value associated to the given key, or None if the key
+    /// is not contained. If the value is modified, the Hash of the Leaf is refreshed once the
+    /// access is dropped, and the cached Hashes along the path are discarded.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<ValueMut<'_, K, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        match self.root.find_path_mut(key, 0) {
+            (NodeGeneric::Leaf(n), _, path_hashes) if n.get_key().borrow() == key => {
+                Some(ValueMut::new(n, path_hashes))
+            }
+            _ => None,
+        }
     }
 
     /// Returns a Result which contains: a reference of the NodeGeneric associated 
     /// to the given key, if the key is contained; Err(()) otherwise. 
-    /// Panics if the given key is not associated to any value in the MerkleTree.
-    pub fn get_node(&self, key: K) -> Result<&NodeGeneric<K, V>, ()> {
-        self.root.find_path(&key, 0)
+    pub fn get_node<Q>(&self, key: &Q) -> Result<&NodeGeneric<K, V>, ()>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.root.find_path(key, 0)
+    }
+
+    /// Returns a reference of the value associated to the given key, 
+    /// or None if the key is not contained.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        match self.get_node(key) {
+            Ok(NodeGeneric::Leaf(n)) => Some(n.get_value()),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the given key is contained in the MerkleTree.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.get_node(key).is_ok()
+    }
+
+    /// Returns the number of entries of the MerkleTree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the MerkleTree holds no entry.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a reference of the salt of the Leaf associated to the given key,
+    /// or None if the Leaf is not salted or the key is not contained.
+    pub fn get_salt<Q>(&self, key: &Q) -> Option<&Hash>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        match self.get_node(key) {
+            Ok(NodeGeneric::Leaf(n)) => n.get_salt(),
+            _ => None,
+        }
     }
 
     /// Returns a reference of the value associated to the given key.
     /// Panics if the given key is not associated to any value in the MerkleTree.
-    pub fn get_value(&self, key: K) -> &V {
-        match self.get_node(key).unwrap() {
-            NodeGeneric::Leaf(n) => n.get_value(),
-            _ => panic!(),
+    pub fn get_value<Q>(&self, key: &Q) -> &V
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.get(key).unwrap()
+    }
+}
+
+impl<K, V> Default for MerkleTree<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    fn default() -> Self {
+        MerkleTree::new()
+    }
+}
+
+impl<K, V> MerkleTree<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    /// Returns true if the MerkleTree is in its canonical shape: no Internal node other than 
+    /// the root holds less than two Leaves, no cached Hash is stale and every Leaf follows 
+    /// the path given by the hash of its key.
+    pub fn is_canonical(&self) -> bool {
+        self.find_violations().is_empty()
+    }
+
+    /// Returns every breach of the canonical shape of the MerkleTree.
+    pub fn find_violations(&self) -> Vec<Violation> {
+        let mut violations = Vec::<Violation>::new();
+        match self.get_root() {
+            NodeGeneric::Internal(_) => (),
+            _ => violations.push(Violation::RootNotInternal),
         }
+        self.root.find_violations(&mut Vec::new(), &mut violations);
+        violations
+    }
+
+    /// Rebuilds the MerkleTree in its canonical shape. If several Leaves share the 
+    /// same key, or collide, only the rightmost one is kept. Cached Hashes are discarded.
+    pub fn canonicalize(&mut self) {
+        let root = std::mem::replace(&mut self.root, Box::new(NodeGeneric::new()));
+        let mut leaves = Vec::<Leaf<K, V>>::new();
+        (*root).into_leaves(&mut leaves);
+
+        let mut positions = HashMap::<[u8; 32], usize>::new();
+        let mut unique = Vec::<Leaf<K, V>>::with_capacity(leaves.len());
+        for leaf in leaves {
+            let mut path = leaf.get_key().key_path();
+            // the last bit of the path of a key is never followed
+            path[31] &= 0xfe;
+            match positions.entry(path) {
+                hash_map::Entry::Occupied(e) => unique[*e.get()] = leaf,
+                hash_map::Entry::Vacant(e) => {
+                    e.insert(unique.len());
+                    unique.push(leaf);
+                }
+            }
+        }
+        self.len = unique.len();
+        *self.root = NodeGeneric::from_leaves(unique, 0);
     }
 }
 
 impl<K, V> MerkleTree<K, V>
 where
-    K: Serialize + Clone + Eq,
+    K: Serialize + KeyPath + Clone + Eq,
     V: Serialize + Clone,
 {
-    /// Returns a Proof for the specific given key.
+    /// Returns the Changes turning the MerkleTree into the given one. Branches whose cached
+    /// Hashes are equal are skipped without being visited, so both MerkleTrees should have 
+    /// their hashes computed for diffing large MerkleTrees to be cheap.
+    pub fn diff(&self, other: &MerkleTree<K, V>) -> Vec<Change<K, V>> {
+        let mut changes = Vec::<Change<K, V>>::new();
+        self.root.diff(&other.root, &mut changes);
+        changes
+    }
+
+    /// Returns the independent subtrees of the MerkleTree found at the given split depth,
+    /// each one together with the prefix of bits (true <--> Right, false <--> Left) leading
+    /// to it. Empty subtrees are left out. Every subtree can be grafted back through graft.
+    pub fn split_at_prefix(self, split_depth: u8) -> Vec<(Vec<bool>, NodeGeneric<K, V>)> {
+        let mut subtrees = Vec::<(Vec<bool>, NodeGeneric<K, V>)>::new();
+        (*self.root).into_subtrees(split_depth, &mut Vec::new(), &mut subtrees);
+        subtrees
+    }
+
+    /// Returns Ok(()) once every Leaf whose key hash starts with the given prefix of bits has
+    /// been replaced by the Leaves of the given subtree, possibly computed on another machine.
+    /// Returns Err(()), leaving the MerkleTree untouched, if a Leaf of the subtree does not 
+    /// belong under the prefix or if two of its Leaves share the same key or collide.
+    pub fn graft(&mut self, prefix: &[bool], subtree: NodeGeneric<K, V>) -> Result<(), ()> {
+        let mut leaves = Vec::<Leaf<K, V>>::new();
+        subtree.into_leaves(&mut leaves);
+        self.replace_range(prefix, leaves)
+    }
+
+    /// Returns Ok(()) once every Leaf whose key hash starts with the given prefix of bits has
+    /// been replaced by the given Leaves. Returns Err(()), leaving the MerkleTree untouched,
+    /// if a given Leaf does not belong under the prefix or if two of them share the same key
+    /// or collide.
+    pub fn replace_range(&mut self, prefix: &[bool], leaves: Vec<Leaf<K, V>>) -> Result<(), ()> {
+        let mut paths = HashSet::<[u8; 32]>::new();
+        for leaf in leaves.iter() {
+            let mut path = leaf.get_key().key_path();
+            // the last bit of the path of a key is never followed
+            path[31] &= 0xfe;
+            if !starts_with_prefix(&path, prefix) || !paths.insert(path) {
+                return Err(());
+            }
+        }
+        self.len += leaves.len();
+        self.len -= self.root.replace_range(prefix, 0, leaves);
+        Ok(())
+    }
+}
+
+impl<K, V> MerkleTree<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    /// Returns a Proof for the specific given key. Only the Hashes discarded since the last
+    /// call to compute_hashes are computed again, as compute_hashes does.
     /// The Proof contains an empty vector of Siblings if the key is not contained.
     pub fn prove(&mut self, key: K) -> Proof {
+        self.compute_hashes();
+        match self.try_prove(key) {
+            Ok(proof) => proof,
+            Err(StaleHashes) => unreachable!("the Hashes have just been computed"),
+        }
+    }
+
+    /// Returns a Result which contains: a Proof for the specific given key, as prove does;
+    /// Err(StaleHashes) if the Hash of an Internal node needed by the Proof has been discarded
+    /// by a modification since the last call to compute_hashes.
+    pub fn try_prove(&self, key: K) -> Result<Proof, StaleHashes> {
         let mut siblings = Vec::<Sibling>::new();
-        let node_err = self.get_node(key.clone());
+        if self.get_node(&key).is_err() {
+            return Ok(Proof::new(siblings));
+        }
+
+        self.root.get_siblings(&key, 0, &mut siblings)?;
+        siblings.reverse();
+        Ok(Proof::new(siblings))
+    }
 
-        let mut_node_err = match node_err {
-            Ok(n) => {}
-            Err(e) => return Proof::new(siblings),
+    /// Returns an OpenedProof for the specific given key, carrying the key, the value and the
+    /// salt of its Leaf together with the unsigned Hash of the root, or None if the key is not contained.
+    /// Only the Hashes discarded since the last call to compute_hashes are computed again.
+    pub fn prove_opened<Q>(&mut self, key: &Q) -> Option<OpenedProof<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.compute_hashes();
+        let leaf = match self.get_node(key) {
+            Ok(NodeGeneric::Leaf(n)) => n,
+            _ => return None,
+        };
+        let proof = match self.try_prove(leaf.get_key().clone()) {
+            Ok(proof) => proof,
+            Err(StaleHashes) => unreachable!("the Hashes have just been computed"),
         };
+        Some(OpenedProof::new(
+            leaf.get_key().clone(),
+            leaf.get_value().clone(),
+            leaf.get_salt().cloned(),
+            proof.get_siblings().clone(),
+            self.root.get_current_hash(),
+        ))
+    }
+
+    /// Returns true if the MerkleTree has been modified since the last call to compute_hashes,
+    /// so that some Hashes are to be computed again before try_prove can generate a Proof.
+    pub fn has_stale_hashes(&self) -> bool {
+        self.root.get_current_hash().is_none()
+    }
+
+    /// Returns a RangeProof for every key whose hash starts with the given prefix of bits
+    /// (true <--> Right, false <--> Left). Salted Leaves are given by their Hash only.
+    /// Returns Err(RangeError::PrefixTooLong) if the prefix is longer than MAX_PREFIX_LEN bits.
+    /// Only the Hashes discarded since the last call to compute_hashes are computed again.
+    pub fn prove_range(&mut self, prefix_bits: &[bool]) -> Result<RangeProof<K, V>, RangeError> {
+        self.compute_hashes();
+        match self.try_prove_range(prefix_bits) {
+            Err(RangeError::StaleHashes) => unreachable!("the Hashes have just been computed"),
+            result => result,
+        }
+    }
 
-        self.root.get_siblings(&key, 0, &mut siblings);
+    /// Returns a Result which contains: a RangeProof for the given prefix of bits, as
+    /// prove_range does; Err(RangeError::PrefixTooLong) if the prefix is longer than
+    /// MAX_PREFIX_LEN bits; Err(RangeError::StaleHashes) if the Hash of an Internal node needed
+    /// by the RangeProof has been discarded by a modification since the last call to compute_hashes.
+    pub fn try_prove_range(&self, prefix_bits: &[bool]) -> Result<RangeProof<K, V>, RangeError> {
+        if prefix_bits.len() > MAX_PREFIX_LEN {
+            return Err(RangeError::PrefixTooLong);
+        }
+        let mut siblings = Vec::<Sibling>::new();
+        let mut leaves = Vec::<Leaf<K, V>>::new();
+
+        self.root.get_range(prefix_bits, 0, &mut siblings, &mut leaves)?;
         siblings.reverse();
-        Proof::new(siblings)
+        let leaves = leaves.iter().map(RangeLeaf::from_leaf).collect();
+        Ok(RangeProof::new(prefix_bits.to_vec(), leaves, siblings))
     }
 
-    /// Returns the hash of the root of the MerkleTree. Recursively computes 
-    /// and assigns the corresponding Hash to every internal node. 
+    /// Returns the hash of the root of the MerkleTree. Computes and assigns the corresponding
+    /// Hash to every internal node modified since the last call: the other Hashes are kept.
     pub fn compute_hashes(&mut self) -> Hash {
-        self.root.compute_hashes()
+        self.root.update_hashes()
+    }
+
+    /// Returns the hash of the root of the MerkleTree, bit-identical to the one returned by 
+    /// compute_hashes. The two subtrees of every Internal node less deep than the given split
+    /// depth are hashed on separate threads, so that at most 2^split_depth threads are used.
+    pub fn compute_hashes_parallel(&mut self, split_depth: u8) -> Hash
+    where
+        K: Send,
+        V: Send,
+    {
+        self.root.compute_hashes_parallel(split_depth)
     }
 
     /// Returns a Proof for the specific given key.
     /// Sequentially invokes the methods:
     /// compute_hashes(&mut self) -> Hash  and
     /// prove(&mut self, key: K) -> Proof  
+    /// Only the Hashes discarded since the last call to compute_hashes are computed again.
     /// The Proof contains an empty vector of Siblings if the key is not contained.
     pub fn compute_hashes_prove(&mut self, key: K) -> Proof {
         self.compute_hashes();
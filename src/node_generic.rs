@@ -1,3 +1,4 @@
+use crate::commitment::hash_value;
 use crate::util::*;
 use crate::structs::*;
 use serde::{Deserialize, Serialize};
@@ -41,13 +42,29 @@ where
 {
     k: K,
     v: V,
+    salt: Option<Hash>,
     my_hash: Hash,
 }
 
 
 impl<K, V> NodeGeneric<K, V>
 where
-    K: Serialize + Clone + Eq,
+    K: Serialize,
+    V: Serialize,
+{
+    /// Returns the number of Leaves in the subtree of the node invoking this method.
+    pub fn count_leaves(&self) -> usize {
+        match self {
+            NodeGeneric::Internal(n) => n.left.count_leaves() + n.right.count_leaves(),
+            NodeGeneric::Leaf(_) => 1,
+            NodeGeneric::Empty(_) => 0,
+        }
+    }
+}
+
+impl<K, V> NodeGeneric<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
     V: Serialize + Clone,
 {
     /// Returns a new Leaf as a NodeGeneric.
@@ -65,12 +82,37 @@ where
     /// assigns the corresponding Hash to every internal node in the underlying MerklTree. 
     pub fn compute_hashes(&mut self) -> Hash {
         match self {
-            NodeGeneric::Empty(n) => Empty::get_hash(),
+            NodeGeneric::Empty(_) => Empty::get_hash(),
             NodeGeneric::Leaf(n) => n.my_hash,
             NodeGeneric::Internal(n) => n.compute_hashes(),
         }
     }
 
+    /// Returns the hash of the node invoking this method, equal to the one returned by
+    /// compute_hashes as long as every Hash discarded by a modification is set to None.
+    /// Only the Internal nodes whose Hash is None are visited and assigned a Hash again.
+    pub fn update_hashes(&mut self) -> Hash {
+        match self {
+            NodeGeneric::Empty(_) => Empty::get_hash(),
+            NodeGeneric::Leaf(n) => n.my_hash,
+            NodeGeneric::Internal(n) => n.update_hashes(),
+        }
+    }
+
+    /// Returns the hash of the node invoking this method, bit-identical to the one returned by
+    /// compute_hashes. The two subtrees of every Internal node less deep than the given split 
+    /// depth (relative to the node invoking this method) are hashed on separate threads.
+    pub fn compute_hashes_parallel(&mut self, split_depth: u8) -> Hash
+    where
+        K: Send,
+        V: Send,
+    {
+        match self {
+            NodeGeneric::Internal(n) => n.compute_hashes_parallel(split_depth),
+            _ => self.compute_hashes(),
+        }
+    }
+
     /// Returns the Hash of a NodeGeneric recursively computing the Hash of each node 
     /// in the underlying MerkleTree.
     pub fn get_hash(&self) -> Hash {
@@ -83,58 +125,430 @@ where
 
     /// Returns a Result which contains: a reference of the NodeGeneric associated 
     /// to the given key, if the key is contained; Err(()) otherwise. 
-    /// Panics if the given key is not associated to any value in the MerkleTree.
-    pub fn find_path<Q: ?Sized>(&self, key: &Q, index: u8) -> Result<&NodeGeneric<K, V>, ()>
+    pub fn find_path<Q>(&self, key: &Q, index: u8) -> Result<&NodeGeneric<K, V>, ()>
     where
         K: Borrow<Q>,
-        Q: Serialize + Eq,
+        Q: ?Sized + KeyPath + Eq,
     {
-        let my_hash = self.get_hash();
-        let hash_of_given_key = hash(&key).unwrap();
-
-        match self {
-            NodeGeneric::Internal(n) => n.find_path(key, index),
-            NodeGeneric::Leaf(n) => {
-                if hash(&n.k).unwrap() == hash(&key).unwrap() {
-                    Ok(&self)
-                } else {
-                    Err(())
+        let key_path = key.key_path();
+        let mut node = self;
+        let mut index = index;
+        loop {
+            match node {
+                NodeGeneric::Internal(n) => {
+                    node = if get_bit_direction(&key_path, index) {
+                        n.get_right()
+                    } else {
+                        n.get_left()
+                    };
+                    index += 1;
                 }
+                NodeGeneric::Leaf(n) if n.k.borrow() == key => return Ok(node),
+                _ => return Err(()),
             }
-            _ => Err(()),
         }
     }
 
-    /// Returns the created Leaf node as NodeGeneric. 
-    /// Returns an already existing Leaf as NodeGeneric if the key to be inserted
-    /// already exists. Inserts a new Leaf in the underlying MerkleTree if the key is not 
+    /// Returns the Leaf associated to the given key, or None if the key is not contained.
+    /// Removes the Leaf from the underlying MerkleTree, which is kept in its canonical shape,
+    /// and discards the cached Hashes of the Internal nodes along the path.
+    pub fn remove<Q>(&mut self, key: &Q, index: u8) -> Option<Leaf<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.remove_at(key, &key.key_path(), index)
+    }
+
+    /// Returns the Leaf associated to the given key, whose path is given, or None if the key 
+    /// is not contained. Removes the Leaf from the underlying MerkleTree.
+    fn remove_at<Q>(&mut self, key: &Q, key_path: &[u8; 32], index: u8) -> Option<Leaf<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        let removed = match self {
+            NodeGeneric::Internal(n) => n.remove(key, key_path, index),
+            NodeGeneric::Leaf(n) if n.k.borrow() == key => {
+                Some(std::mem::take(self).into_leaf())
+            }
+            _ => None,
+        };
+        if removed.is_some() && index > 0 {
+            self.collapse();
+        }
+        removed
+    }
+
+    /// Returns the value previously associated to the given key, or None if the key was not 
+    /// contained. Inserts a new Leaf in the underlying MerkleTree if the key is not 
     /// contained or substitutes the current value associated to the given key.
     /// Panics if there is a collision.
-    pub fn insert(&mut self, key_to_add: K, value_to_add: V, index: u8) -> NodeGeneric<K, V> {
+    pub fn insert(&mut self, key_to_add: K, value_to_add: V, index: u8) -> Option<V> {
+        self.insert_leaf(Leaf::new(key_to_add, value_to_add), index)
+            .map(|old| old.v)
+    }
+
+    /// Returns the Leaf previously associated to the same key, or None if the key was not
+    /// contained. Inserts the given Leaf in the underlying MerkleTree if its key is not 
+    /// contained or substitutes the Leaf associated to the same key, Hash included.
+    /// The cached Hashes of the Internal nodes along the path are discarded.
+    /// Panics if there is a collision.
+    pub fn insert_leaf(&mut self, leaf_to_add: Leaf<K, V>, index: u8) -> Option<Leaf<K, V>> {
+        match self.try_insert_leaf(leaf_to_add, index) {
+            Ok(replaced) => replaced,
+            Err(Collision) => {
+                panic!("followed the same path: different keys but same hash ---> Collision")
+            }
+        }
+    }
+
+    /// Returns a Result which contains: the Leaf previously associated to the same key, or 
+    /// None if the key was not contained; Err(Collision) if the key of the given Leaf collides 
+    /// with another key. Behaves as insert_leaf otherwise. The Leaves of the underlying 
+    /// MerkleTree are left untouched in case of a collision.
+    pub fn try_insert_leaf(&mut self, leaf_to_add: Leaf<K, V>, index: u8) -> Result<Option<Leaf<K, V>>, Collision> {
+        let key_path = leaf_to_add.k.key_path();
+        self.try_insert_leaf_at(leaf_to_add, &key_path, index)
+    }
+
+    /// Returns a Result which contains: the Leaf previously associated to the same key, or 
+    /// None if the key was not contained; Err(Collision) if the key of the given Leaf, whose 
+    /// path is given, collides with another key. 
+    fn try_insert_leaf_at(
+        &mut self,
+        leaf_to_add: Leaf<K, V>,
+        key_path: &[u8; 32],
+        index: u8,
+    ) -> Result<Option<Leaf<K, V>>, Collision> {
         match self {
-            NodeGeneric::Internal(n) => n.insert(key_to_add, value_to_add, index),
-            NodeGeneric::Leaf(n) => n.insert(key_to_add, value_to_add, index),
-            NodeGeneric::Empty(n) => n.insert(key_to_add, value_to_add, index),
+            NodeGeneric::Internal(n) => n.try_insert_leaf(leaf_to_add, key_path, index),
+            NodeGeneric::Leaf(n) if n.k == leaf_to_add.k => Ok(Some(std::mem::replace(n, leaf_to_add))),
+            NodeGeneric::Leaf(n) => {
+                if index == 255 || shared_bits(&n.k.key_path(), key_path) >= 255 {
+                    return Err(Collision);
+                }
+                // this Leaf node is at depth < 255 but holds another key, so create a branch Internal
+                // node, move the precedent Leaf node more into depth and create the Empty sibling.
+                let old_leaf = std::mem::take(self).into_leaf();
+                let mut new_internal = old_leaf.into_branch(index);
+                let replaced = new_internal.try_insert_leaf(leaf_to_add, key_path, index);
+                *self = new_internal.into();
+                replaced
+            }
+            NodeGeneric::Empty(_) => {
+                *self = leaf_to_add.into();
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns the node, at the given depth, where the given key is to be found or inserted: 
+    /// either a Leaf or an Empty node, together with its depth and the cached Hashes of the
+    /// Internal nodes along the path, to be discarded by the caller if the node is modified.
+    pub fn find_path_mut<Q>(
+        &mut self,
+        key: &Q,
+        index: u8,
+    ) -> (&mut NodeGeneric<K, V>, u8, Vec<&mut Option<Hash>>)
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        let key_path = key.key_path();
+        let mut node = self;
+        let mut index = index;
+        let mut path_hashes = Vec::<&mut Option<Hash>>::new();
+        loop {
+            match node {
+                NodeGeneric::Internal(Internal { left, right, my_hash }) => {
+                    path_hashes.push(my_hash);
+                    node = if get_bit_direction(&key_path, index) {
+                        right
+                    } else {
+                        left
+                    };
+                    index += 1;
+                }
+                other => return (other, index, path_hashes),
+            }
         }
     }
 
     /// Recursively updates an initially empty vector of Siblings. While researching the given
     /// key, a new Sibling is added to the vector every time the depth increases by one unit. 
-    pub fn get_siblings<Q>(&self, key: &Q, index: u8, siblings: &mut Vec<Sibling>)
+    /// Returns Err(StaleHashes) if the Hash of a sibling Internal node has not been computed.
+    pub fn get_siblings<Q>(
+        &self,
+        key: &Q,
+        index: u8,
+        siblings: &mut Vec<Sibling>,
+    ) -> Result<(), StaleHashes>
     where
         K: Borrow<Q>,
-        Q: Serialize + Eq,
+        Q: ?Sized + KeyPath + Eq,
     {
         match &self {
-            NodeGeneric::Internal(n) => n.get_siblings(key, index, siblings),
-            NodeGeneric::Leaf(n) => (),
-            NodeGeneric::Empty(n) => (),
-            _ => panic!(),
+            NodeGeneric::Internal(n) => n.get_siblings(&key.key_path(), index, siblings),
+            NodeGeneric::Leaf(_) | NodeGeneric::Empty(_) => Ok(()),
         }
     }
     
+    /// Recursively updates the given vectors of Siblings and Leaves. While following the given
+    /// prefix, a new Sibling is added to the vector every time the depth increases by one unit. 
+    /// Once the prefix is exhausted, or there is no Internal node left to follow it, a copy of
+    /// every Leaf below is added to the vector of Leaves.
+    /// Returns Err(StaleHashes) if the Hash of a sibling Internal node has not been computed.
+    pub fn get_range(
+        &self,
+        prefix: &[bool],
+        index: u8,
+        siblings: &mut Vec<Sibling>,
+        leaves: &mut Vec<Leaf<K, V>>,
+    ) -> Result<(), StaleHashes> {
+        match self {
+            NodeGeneric::Internal(n) if (index as usize) < prefix.len() => {
+                n.get_range(prefix, index, siblings, leaves)
+            }
+            _ => {
+                self.get_leaves(leaves);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the Hash of the node invoking this method, as cached by the last call to 
+    /// compute_hashes. Panics if the Hash of an Internal node has not been computed yet.
+    pub fn get_cached_hash(&self) -> Hash {
+        match self {
+            NodeGeneric::Internal(n) => n.my_hash.unwrap(),
+            NodeGeneric::Leaf(n) => n.my_hash,
+            NodeGeneric::Empty(_) => Empty::get_hash(),
+        }
+    }
+
+    /// Returns the Hash of the node invoking this method, as cached by the last call to
+    /// compute_hashes, or None if the Hash of an Internal node has not been computed yet.
+    pub fn get_current_hash(&self) -> Option<Hash> {
+        match self {
+            NodeGeneric::Internal(n) => n.my_hash,
+            NodeGeneric::Leaf(n) => Some(n.my_hash),
+            NodeGeneric::Empty(_) => Some(Empty::get_hash()),
+        }
+    }
+
+    /// Recursively appends to the given vector the Changes turning the subtree of the node
+    /// invoking this method into the subtree of the given node, at the same depth. Branches 
+    /// whose cached Hashes are equal on both sides are skipped.
+    pub fn diff(&self, other: &NodeGeneric<K, V>, changes: &mut Vec<Change<K, V>>) {
+        match (self.get_current_hash(), other.get_current_hash()) {
+            (Some(h1), Some(h2)) if h1 == h2 => return,
+            _ => (),
+        }
+        if let (NodeGeneric::Internal(n1), NodeGeneric::Internal(n2)) = (self, other) {
+            n1.get_left().diff(n2.get_left(), changes);
+            n1.get_right().diff(n2.get_right(), changes);
+            return;
+        }
+
+        // at least one side holds at most one Leaf, so pairing the Leaves is cheap
+        let mut old_leaves = Vec::<Leaf<K, V>>::new();
+        let mut new_leaves = Vec::<Leaf<K, V>>::new();
+        self.get_leaves(&mut old_leaves);
+        other.get_leaves(&mut new_leaves);
+        Leaf::diff_leaves(old_leaves, new_leaves, changes);
+    }
+
+    /// Appends a copy of every Leaf in the subtree of the node invoking this method to the given
+    /// vector, from the leftmost to the rightmost.
+    pub fn get_leaves(&self, leaves: &mut Vec<Leaf<K, V>>) {
+        match self {
+            NodeGeneric::Internal(n) => {
+                n.get_left().get_leaves(leaves);
+                n.get_right().get_leaves(leaves);
+            }
+            NodeGeneric::Leaf(n) => leaves.push(n.clone()),
+            NodeGeneric::Empty(_) => (),
+        }
+    }
+
+    /// Returns the number of Leaves in the subtree of the node invoking this method, together 
+    /// with its Hash. Every breach of the canonical shape met in the subtree is appended to the 
+    /// given vector of Violations. The given path leads from the root to the node invoking this method.
+    pub fn find_violations(&self, path: &mut Vec<bool>, violations: &mut Vec<Violation>) -> (usize, Hash) {
+        match self {
+            NodeGeneric::Empty(_) => (0, Empty::get_hash()),
+            NodeGeneric::Leaf(n) => {
+                if !starts_with_prefix(&n.k.key_path(), path) {
+                    violations.push(Violation::MisplacedLeaf(path.clone()));
+                }
+                (1, n.my_hash)
+            }
+            NodeGeneric::Internal(n) => {
+                path.push(false);
+                let (l_count, l_hash) = n.get_left().find_violations(path, violations);
+                path.pop();
+                path.push(true);
+                let (r_count, r_hash) = n.get_right().find_violations(path, violations);
+                path.pop();
+
+                let this_hash = Internal::<K, V>::create_hash(l_hash, r_hash);
+                // only the root is allowed to hold less than two Leaves
+                if !path.is_empty() && l_count + r_count < 2 {
+                    violations.push(Violation::NonMinimalInternal(path.clone()));
+                }
+                if let Some(h) = n.my_hash {
+                    if h != this_hash {
+                        violations.push(Violation::StaleHash(path.clone()));
+                    }
+                }
+                (l_count + r_count, this_hash)
+            }
+        }
+    }
+
+    /// Moves every Leaf in the subtree of the node invoking this method into the given vector,
+    /// from the leftmost to the rightmost.
+    pub fn into_leaves(self, leaves: &mut Vec<Leaf<K, V>>) {
+        match self {
+            NodeGeneric::Internal(n) => {
+                (*n.left).into_leaves(leaves);
+                (*n.right).into_leaves(leaves);
+            }
+            NodeGeneric::Leaf(n) => leaves.push(n),
+            NodeGeneric::Empty(_) => (),
+        }
+    }
+
+    /// Moves every non-empty subtree found at the given split depth into the given vector, 
+    /// together with the prefix of bits leading to it. A Leaf less deep than the split depth 
+    /// is moved as a subtree of its own, under the prefix given by the hash of its key.
+    /// The given path leads from the root to the node invoking this method.
+    pub fn into_subtrees(
+        self,
+        split_depth: u8,
+        path: &mut Vec<bool>,
+        subtrees: &mut Vec<(Vec<bool>, NodeGeneric<K, V>)>,
+    ) {
+        if path.len() == split_depth as usize {
+            match self {
+                NodeGeneric::Empty(_) => (),
+                n => subtrees.push((path.clone(), n)),
+            }
+            return;
+        }
+        match self {
+            NodeGeneric::Internal(n) => {
+                path.push(false);
+                (*n.left).into_subtrees(split_depth, path, subtrees);
+                path.pop();
+                path.push(true);
+                (*n.right).into_subtrees(split_depth, path, subtrees);
+                path.pop();
+            }
+            NodeGeneric::Leaf(n) => {
+                let key_path = n.k.key_path();
+                let prefix = (0..split_depth).map(|i| get_bit_direction(&key_path, i)).collect();
+                subtrees.push((prefix, n.into()));
+            }
+            NodeGeneric::Empty(_) => (),
+        }
+    }
+
+    /// Returns the number of Leaves removed. Replaces every Leaf whose key hash starts with the 
+    /// given prefix of bits by the given Leaves, which must all belong under that prefix. The 
+    /// subtree of the node invoking this method, at the given depth, is kept in its canonical
+    /// shape and the Hashes along the prefix are discarded.
+    /// Panics if there is a collision.
+    pub fn replace_range(&mut self, prefix: &[bool], index: u8, mut leaves: Vec<Leaf<K, V>>) -> usize {
+        if index as usize == prefix.len() {
+            let removed = self.count_leaves();
+            *self = NodeGeneric::from_leaves(leaves, index);
+            return removed;
+        }
+        match self {
+            NodeGeneric::Internal(n) => {
+                n.my_hash = None;
+                let removed = if prefix[index as usize] {
+                    n.get_mut_right().replace_range(prefix, index + 1, leaves)
+                } else {
+                    n.get_mut_left().replace_range(prefix, index + 1, leaves)
+                };
+                if index > 0 {
+                    self.collapse();
+                }
+                removed
+            }
+            NodeGeneric::Leaf(n) => {
+                // the path ends before the prefix: keep the Leaf only if it lies outside of it
+                let removed = if starts_with_prefix(&n.k.key_path(), prefix) {
+                    1
+                } else {
+                    leaves.push(n.clone());
+                    0
+                };
+                *self = NodeGeneric::from_leaves(leaves, index);
+                removed
+            }
+            NodeGeneric::Empty(_) => {
+                *self = NodeGeneric::from_leaves(leaves, index);
+                0
+            }
+        }
+    }
+
+    /// Replaces the Internal node invoking this method by its only Leaf, or by an Empty node,
+    /// if it holds less than two Leaves. Both its children must be in their canonical shape.
+    fn collapse(&mut self) {
+        let replacement = match self {
+            NodeGeneric::Internal(n) => match (&mut *n.left, &mut *n.right) {
+                (NodeGeneric::Internal(_), _)
+                | (_, NodeGeneric::Internal(_))
+                | (NodeGeneric::Leaf(_), NodeGeneric::Leaf(_)) => return,
+                (NodeGeneric::Empty(_), other) | (other, NodeGeneric::Empty(_)) => {
+                    std::mem::take(other)
+                }
+            },
+            _ => return,
+        };
+        *self = replacement;
+    }
+
+    /// Returns the canonical subtree, at the given depth, holding exactly the given Leaves.
+    /// The Leaves must have distinct keys. An Internal node is returned at depth 0 even if 
+    /// less than two Leaves are given, since the root of a MerkleTree is always Internal.
+    /// Panics if there is a collision.
+    pub fn from_leaves(leaves: Vec<Leaf<K, V>>, index: u8) -> Self {
+        if index > 0 && leaves.len() < 2 {
+            return match leaves.into_iter().next() {
+                Some(leaf) => leaf.into(),
+                None => Empty::new().into(),
+            };
+        } else if index == 255 {
+            panic!("followed the same path: different keys but same hash ---> Collision");
+        }
+
+        let (right, left): (Vec<_>, Vec<_>) = leaves
+            .into_iter()
+            .partition(|leaf| get_bit_direction(&leaf.k.key_path(), index));
+        Internal::new(
+            NodeGeneric::from_leaves(left, index + 1),
+            NodeGeneric::from_leaves(right, index + 1),
+            None,
+        )
+        .into()
+    }
+
     /// Returns a Leaf node from a NodegeGeneric.
-    pub fn to_leaf(self) -> Leaf<K, V> {
+    pub fn into_leaf(self) -> Leaf<K, V> {
+        match self {
+            NodeGeneric::Leaf(n) => n,
+            _ => panic!("Node which is not a Leaf!"),
+        }
+    }
+
+    /// Returns a mutable reference to a Leaf node from a NodeGeneric.
+    pub fn get_mut_leaf(&mut self) -> &mut Leaf<K, V> {
         match self {
             NodeGeneric::Leaf(n) => n,
             _ => panic!("Node which is not a Leaf!"),
@@ -142,7 +556,7 @@ where
     }
 
     /// Returns an Internal node from a NodeGeneric.
-    pub fn to_internal(self) -> Internal<K, V> {
+    pub fn into_internal(self) -> Internal<K, V> {
         match self {
             NodeGeneric::Internal(n) => n,
             _ => panic!("Node which is not a Internal!"),
@@ -150,7 +564,7 @@ where
     }
 
     /// Returns an Empty node from a NodeGeneric.
-    pub fn to_empty(self) -> Empty {
+    pub fn into_empty(self) -> Empty {
         match self {
             NodeGeneric::Empty(n) => n,
             _ => panic!("Node which is not a Empty!"),
@@ -159,9 +573,19 @@ where
 
 }
 
+impl<K, V> Default for NodeGeneric<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    fn default() -> Self {
+        NodeGeneric::new()
+    }
+}
+
 impl<K, V> From<&mut Internal<K, V>> for NodeGeneric<K, V>
 where
-    K: Serialize + Clone + Eq,
+    K: Serialize + KeyPath + Clone + Eq,
     V: Serialize + Clone,
 {
     fn from(i: &mut Internal<K, V>) -> Self {
@@ -171,7 +595,7 @@ where
 
 impl<K, V> From<Internal<K, V>> for NodeGeneric<K, V>
 where
-    K: Serialize + Clone + Eq,
+    K: Serialize + KeyPath + Clone + Eq,
     V: Serialize + Clone,
 {
     fn from(i: Internal<K, V>) -> Self {
@@ -181,7 +605,18 @@ where
 
 impl<K, V> Internal<K, V>
 where
-    K: Serialize + Clone + Eq,
+    K: Serialize,
+    V: Serialize,
+{
+    /// Returns the Hash of an Internal node, given a key and a value.
+    pub fn create_hash(l_hash: Hash, r_hash: Hash) -> Hash {
+        hash(&(l_hash, r_hash)).unwrap()
+    }
+}
+
+impl<K, V> Internal<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
     V: Serialize + Clone,
 {
     /// Returns a new Internal node as a NodeGeneric.
@@ -198,7 +633,7 @@ where
     fn compute_hashes<Q>(&mut self) -> Hash
     where
         K: Borrow<Q>,
-        Q: Serialize + Eq,
+        Q: KeyPath + Eq,
     {
         let this_hash = Internal::<K, V>::create_hash(
             self.get_mut_left().compute_hashes(),
@@ -207,15 +642,53 @@ where
         self.set_hash(Some(this_hash))
     }
 
-    /// Returns the Hash of an Internal node, given a key and a value.
-    pub fn create_hash(l_hash: Hash, r_hash: Hash) -> Hash {
-        hash(&(l_hash, r_hash)).unwrap()
+    /// Returns the cached Hash of the Internal node if any, otherwise recursively computes
+    /// and assigns the Hash of every Internal node of its subtree whose Hash is None.
+    fn update_hashes(&mut self) -> Hash {
+        if let Some(h) = self.my_hash {
+            return h;
+        }
+        let this_hash = Internal::<K, V>::create_hash(
+            self.get_mut_left().update_hashes(),
+            self.get_mut_right().update_hashes(),
+        );
+        self.set_hash(Some(this_hash))
     }
 
-    /// Returns the reference of an Option containing the current Hash of the Internal node or 
+    /// Returns the hash of the node invoking this method, bit-identical to the one returned by
+    /// compute_hashes. The two subtrees of every Internal node less deep than the given split 
+    /// depth are hashed on separate threads.
+    fn compute_hashes_parallel(&mut self, split_depth: u8) -> Hash
+    where
+        K: Send,
+        V: Send,
+    {
+        let both_internal = matches!(
+            (self.get_left(), self.get_right()),
+            (NodeGeneric::Internal(_), NodeGeneric::Internal(_))
+        );
+        if split_depth == 0 || !both_internal {
+            // not worth a thread: keep descending on the current one
+            let this_hash = Internal::<K, V>::create_hash(
+                self.get_mut_left().compute_hashes_parallel(split_depth.saturating_sub(1)),
+                self.get_mut_right().compute_hashes_parallel(split_depth.saturating_sub(1)),
+            );
+            return self.set_hash(Some(this_hash));
+        }
+
+        let (left, right) = (&mut self.left, &mut self.right);
+        let (l_hash, r_hash) = std::thread::scope(|s| {
+            let l_handle = s.spawn(|| left.compute_hashes_parallel(split_depth - 1));
+            let r_hash = right.compute_hashes_parallel(split_depth - 1);
+            (l_handle.join().unwrap(), r_hash)
+        });
+        self.set_hash(Some(Internal::<K, V>::create_hash(l_hash, r_hash)))
+    }
+
+    /// Returns an Option containing the current Hash of the Internal node or 
     /// None if the Hash has not been calculated yet.
-    pub fn get_current_hash(&self) -> &Option<Hash> {
-        &self.my_hash
+    pub fn get_current_hash(&self) -> Option<Hash> {
+        self.my_hash
     }
 
     /// Returns the Hash of an Internal node by recursively computing the Hash of each node 
@@ -231,84 +704,105 @@ where
         h.unwrap()
     }
 
-    /// Returns a Result which contains: a reference of the NodeGeneric associated 
-    /// to the given key, if the key is contained; Err(()) otherwise. 
-    /// Panics if the given key is not associated to any value in the MerkleTree.
-    fn find_path<Q: ?Sized>(&self, key: &Q, index: u8) -> Result<&NodeGeneric<K, V>, ()>
+    /// Returns the Leaf associated to the given key, whose path is given, or None if the key 
+    /// is not contained. Removes the Leaf from the underlying MerkleTree.
+    fn remove<Q>(&mut self, key: &Q, key_path: &[u8; 32], index: u8) -> Option<Leaf<K, V>>
     where
         K: Borrow<Q>,
-        Q: Serialize + Eq,
+        Q: ?Sized + KeyPath + Eq,
     {
-        let key_hash = hash(&key).unwrap();
-        let direction = get_bit_direction(&key_hash.to_bytes(), index);
+        let direction = get_bit_direction(key_path, index);
 
-        if direction == true {
-            self.get_right().find_path(key, index + 1)
+        let removed = if direction {
+            self.right.remove_at(key, key_path, index + 1)
         } else {
-            self.get_left().find_path(key, index + 1)
+            self.left.remove_at(key, key_path, index + 1)
+        };
+        if removed.is_some() {
+            self.my_hash = None;
         }
-    }
-
-    /// Returns the created Leaf node as NodeGeneric. 
-    /// Returns an already existing Leaf as NodeGeneric if the key to be inserted
-    /// already exists. Inserts a new Leaf in the underlying MerkleTree if the key is not 
-    /// contained or substitutes the current value associated to the given key.
-    /// Panics if there is a collision.
-    fn insert(&mut self, key_to_add: K, value_to_add: V, index: u8) -> NodeGeneric<K, V> {
-        let key_hash = hash(&key_to_add).unwrap();
-        let direction = get_bit_direction(&key_hash.to_bytes(), index);
-
-        let mut side;
-        if direction == true {
-            side = &mut self.right;
+        removed
+    }
+
+    /// Returns a Result which contains: the Leaf previously associated to the same key, or 
+    /// None if the key was not contained; Err(Collision) if the key of the given Leaf, whose 
+    /// path is given, collides with another key. Inserts the given Leaf in the underlying 
+    /// MerkleTree if its key is not contained or substitutes the Leaf associated to the same key.
+    fn try_insert_leaf(
+        &mut self,
+        leaf_to_add: Leaf<K, V>,
+        key_path: &[u8; 32],
+        index: u8,
+    ) -> Result<Option<Leaf<K, V>>, Collision> {
+        let direction = get_bit_direction(key_path, index);
+
+        self.my_hash = None;
+        if direction {
+            self.right.try_insert_leaf_at(leaf_to_add, key_path, index + 1)
         } else {
-            side = &mut self.left;
-        }
-
-        let mut n = NodeGeneric::new();
-        std::mem::swap(&mut n, side);
-
-        match n.insert(key_to_add, value_to_add, index + 1) {
-            n @ _ => {
-                *side = Box::new(n);
-                self.into()
-            }
+            self.left.try_insert_leaf_at(leaf_to_add, key_path, index + 1)
         }
     }
 
-    /// Recursively updates a given vector of Siblings. While researching the given
-    /// key, a new Sibling is added to the vector every time the depth increases by one unit. 
-    fn get_siblings<Q>(&self, key: &Q, index: u8, siblings: &mut Vec<Sibling>)
-    where
-        K: Borrow<Q>,
-        Q: Serialize + Eq,
-    {
-        let key_hash = hash(key).unwrap();
-        let direction = get_bit_direction(&key_hash.to_bytes(), index);
-        if direction == true {
+    /// Recursively updates a given vector of Siblings. While following the given path
+    /// of a key, a new Sibling is added to the vector every time the depth increases by one unit. 
+    /// Returns Err(StaleHashes) if the Hash of a sibling Internal node has not been computed.
+    fn get_siblings(
+        &self,
+        key_path: &[u8; 32],
+        index: u8,
+        siblings: &mut Vec<Sibling>,
+    ) -> Result<(), StaleHashes> {
+        let direction = get_bit_direction(key_path, index);
+        if direction {
             let l_node = self.get_left();
             match l_node {
                 NodeGeneric::Internal(n) => {
-                    siblings.push(Sibling::new(n.my_hash.unwrap(), Left {}.into()))
+                    siblings.push(Sibling::new(n.my_hash.ok_or(StaleHashes)?, Left {}.into()))
                 }
                 NodeGeneric::Leaf(n) => siblings.push(Sibling::new(n.my_hash, Left {}.into())),
-                NodeGeneric::Empty(n) => {
+                NodeGeneric::Empty(_) => {
                     siblings.push(Sibling::new(Empty::get_hash(), Left {}.into()))
                 }
             }
-            self.get_right().get_siblings(key, index + 1, siblings)
+            if let NodeGeneric::Internal(n) = self.get_right() {
+                return n.get_siblings(key_path, index + 1, siblings);
+            }
         } else {
             let r_node = self.get_right();
             match r_node {
                 NodeGeneric::Internal(n) => {
-                    siblings.push(Sibling::new(n.my_hash.unwrap(), Right {}.into()))
+                    siblings.push(Sibling::new(n.my_hash.ok_or(StaleHashes)?, Right {}.into()))
                 }
                 NodeGeneric::Leaf(n) => siblings.push(Sibling::new(n.my_hash, Right {}.into())),
-                NodeGeneric::Empty(n) => {
+                NodeGeneric::Empty(_) => {
                     siblings.push(Sibling::new(Empty::get_hash(), Right {}.into()))
                 }
             }
-            self.get_left().get_siblings(key, index + 1, siblings)
+            if let NodeGeneric::Internal(n) = self.get_left() {
+                return n.get_siblings(key_path, index + 1, siblings);
+            }
+        }
+        Ok(())
+    }
+
+    /// Recursively updates the given vectors of Siblings and Leaves. While following the given
+    /// prefix, a new Sibling is added to the vector every time the depth increases by one unit. 
+    fn get_range(
+        &self,
+        prefix: &[bool],
+        index: u8,
+        siblings: &mut Vec<Sibling>,
+        leaves: &mut Vec<Leaf<K, V>>,
+    ) -> Result<(), StaleHashes> {
+        if prefix[index as usize] {
+            let l_hash = self.get_left().get_current_hash().ok_or(StaleHashes)?;
+            siblings.push(Sibling::new(l_hash, Left {}.into()));
+            self.get_right().get_range(prefix, index + 1, siblings, leaves)
+        } else {
+            let r_hash = self.get_right().get_current_hash().ok_or(StaleHashes)?;
+            siblings.push(Sibling::new(r_hash, Right {}.into()));
+            self.get_left().get_range(prefix, index + 1, siblings, leaves)
         }
     }
 
@@ -337,7 +831,7 @@ where
 
 impl<K, V> From<Leaf<K, V>> for NodeGeneric<K, V>
 where
-    K: Serialize + Clone + Eq,
+    K: Serialize + KeyPath + Clone + Eq,
     V: Serialize + Clone,
 {
     fn from(leaf: Leaf<K, V>) -> Self {
@@ -348,7 +842,7 @@ where
 
 impl<K, V> From<&mut Leaf<K, V>> for NodeGeneric<K, V>
 where
-    K: Serialize + Clone + Eq,
+    K: Serialize + KeyPath + Clone + Eq,
     V: Serialize + Clone,
 {
     fn from(leaf: &mut Leaf<K, V>) -> Self {
@@ -358,63 +852,104 @@ where
 
 impl<K, V> Leaf<K, V>
 where
-    K: Serialize + Clone + Eq,
+    K: Serialize + KeyPath + Clone + Eq,
     V: Serialize + Clone,
 {
+    /// Appends to the given vector the Changes turning the given old Leaves into the given
+    /// new ones, pairing the Leaves by key. Meant for short vectors of Leaves.
+    pub fn diff_leaves(old_leaves: Vec<Leaf<K, V>>, new_leaves: Vec<Leaf<K, V>>, changes: &mut Vec<Change<K, V>>) {
+        for old in old_leaves.iter() {
+            match new_leaves.iter().find(|new| new.k == old.k) {
+                Some(new) if new.my_hash != old.my_hash => changes.push(Change::Changed {
+                    key: old.k.clone(),
+                    old: old.v.clone(),
+                    new: new.v.clone(),
+                }),
+                Some(_) => (),
+                None => changes.push(Change::Removed {
+                    key: old.k.clone(),
+                    value: old.v.clone(),
+                }),
+            }
+        }
+        for new in new_leaves {
+            if !old_leaves.iter().any(|old| old.k == new.k) {
+                changes.push(Change::Added {
+                    key: new.k,
+                    value: new.v,
+                });
+            }
+        }
+    }
+
     /// Returns a new Leaf as a NodeGeneric.
     pub fn new(key: K, value: V) -> Self {
         let my_h = Leaf::create_leaf_hash(key.clone(), value.clone());
         Leaf {
             k: key,
             v: value,
+            salt: None,
+            my_hash: my_h,
+        }
+    }
+
+    /// Returns a new salted Leaf. The salt is mixed into the Hash of the Leaf, so that the key 
+    /// and the value cannot be guessed from it. Only the owner of the Leaf should learn the salt.
+    pub fn new_salted(key: K, value: V, salt: Hash) -> Self {
+        let my_h = Leaf::create_salted_leaf_hash(key.clone(), value.clone(), salt);
+        Leaf {
+            k: key,
+            v: value,
+            salt: Some(salt),
             my_hash: my_h,
         }
     }
 
-    /// Returns the Hash of a Leaf, given a key and a value.
+    /// Returns the Hash of a Leaf, given a key and a value. A ValueHash value is taken as the
+    /// Hash of the value it stands for.
     pub fn create_leaf_hash(key: K, value: V) -> Hash {
         let h1: Hash = hash(&key).unwrap();
-        let h2: Hash = hash(&value).unwrap();
-        hash(&(h1, h2)).unwrap()
+        let h2: Hash = hash_value(&value);
+        Leaf::<K, V>::create_leaf_hash_from(h1, h2)
     }
 
-    /// Returns the Hash of a Leaf.
-    pub fn get_hash(&self) -> Hash {
-        Leaf::create_leaf_hash(&self.k, &self.v)
+    /// Returns the Hash of a salted Leaf, given a key, a value and a salt.
+    pub fn create_salted_leaf_hash(key: K, value: V, salt: Hash) -> Hash {
+        let h1: Hash = hash(&key).unwrap();
+        let h2: Hash = hash_value(&value);
+        Leaf::<K, V>::create_salted_leaf_hash_from(h1, h2, salt)
     }
 
-    /// Returns the given Hash. Assigns the given Hash to the 
-    /// inner variable my_hash.
-    fn set_hash(&mut self, h: Hash) -> Hash {
-        self.my_hash = h;
-        h
+    /// Returns the Hash of a Leaf.
+    pub fn get_hash(&self) -> Hash {
+        self.my_hash
     }
 
-    /// Returns the created Leaf node as NodeGeneric. 
-    /// Returns an already existing Leaf as NodeGeneric if the key to be inserted
-    /// already exists. Inserts a new Leaf in the underlying MerkleTree if the key is not 
-    /// contained or substitutes the current value associated to the given key.
-    /// Panics if there is a collision.
-    fn insert(&mut self, key_to_add: K, value_to_add: V, index: u8) -> NodeGeneric<K, V> {
-        if hash(&key_to_add).unwrap() == hash(&self.k).unwrap() {
-            self.v = value_to_add.clone();
-            self.set_hash(self.get_hash());
-            return self.into();
-        } else if index == 255 {
-            panic!("followed the same path: different keys but same hash ---> Collision")
-        }
-        // this Leaf node is at depth < 255 but the key_to_add != self.k, so create a branch Internal node,
-        // move the precedent Leaf node more into depth and create the Empty sibling.
-        let mut new_internal;
-        let key_hash = hash(&self.k).unwrap();
-        let direction = get_bit_direction(&key_hash.to_bytes(), index);
+    /// Returns a new Internal node, at the given depth, whose only child is the Leaf 
+    /// invoking this method, placed according to the path of its key.
+    fn into_branch(self, index: u8) -> Internal<K, V> {
+        let direction = get_bit_direction(&self.k.key_path(), index);
 
-        if direction == true {
-            new_internal = Internal::new(Empty::new().into(), self.into(), None);
+        if direction {
+            Internal::new(Empty::new().into(), self.into(), None)
         } else {
-            new_internal = Internal::new(self.into(), Empty::new().into(), None);
+            Internal::new(self.into(), Empty::new().into(), None)
         }
-        new_internal.insert(key_to_add, value_to_add, index)
+    }
+
+    /// Recomputes the Hash of the Leaf from its key, its value and its salt,
+    /// once its value has been modified in place.
+    pub(crate) fn refresh_hash(&mut self) {
+        self.my_hash = match self.salt {
+            Some(salt) => Leaf::<&K, &V>::create_salted_leaf_hash(&self.k, &self.v, salt),
+            None => Leaf::<&K, &V>::create_leaf_hash(&self.k, &self.v),
+        };
+    }
+
+    /// Returns a mutable reference to the value of the Leaf. The Hash of the Leaf
+    /// must be refreshed once the value has been modified.
+    pub(crate) fn get_mut_value(&mut self) -> &mut V {
+        &mut self.v
     }
 
     /// Returns a reference to the key of the Leaf.
@@ -426,12 +961,34 @@ where
     pub fn get_value(&self) -> &V {
         &self.v
     }
+
+    /// Returns a reference to the salt of the Leaf, if the Leaf is salted.
+    pub fn get_salt(&self) -> Option<&Hash> {
+        self.salt.as_ref()
+    }
+}
+
+impl<K, V> Leaf<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    /// Returns the Hash of a Leaf, given the Hash of its key and the Hash of its value.
+    pub fn create_leaf_hash_from(key_hash: Hash, value_hash: Hash) -> Hash {
+        hash(&(key_hash, value_hash)).unwrap()
+    }
+
+    /// Returns the Hash of a salted Leaf, given the Hash of its key, the Hash of its value
+    /// and its salt.
+    pub fn create_salted_leaf_hash_from(key_hash: Hash, value_hash: Hash, salt: Hash) -> Hash {
+        hash(&(salt, key_hash, value_hash)).unwrap()
+    }
 }
 
 
 impl<K, V> From<Empty> for NodeGeneric<K, V>
 where
-    K: Serialize + Clone + Eq,
+    K: Serialize + KeyPath + Clone + Eq,
     V: Serialize + Clone,
 {
     fn from(e: Empty) -> Self {
@@ -450,20 +1007,10 @@ impl Empty {
     pub fn get_hash() -> Hash {
         hash(&()).unwrap()
     }
+}
 
-    /// Returns the created Leaf node as NodeGeneric. 
-    /// Returns an already existing Leaf as NodeGeneric if the key to be inserted
-    /// already exists. Inserts a new Leaf in the underlying MerkleTree if the key is not 
-    /// contained or substitutes the current value associated to the given key. 
-    /// Panics if there is a collision.
-    fn insert<K, V>(&mut self, key_to_add: K, value_to_add: V, index: u8) -> NodeGeneric<K, V>
-    where
-        K: Serialize + Clone + Eq,
-        V: Serialize + Clone,
-    {
-        if index == 255 {
-            panic!("followed the same path: different keys but same hash ---> Collision");
-        }
-        Leaf::new(key_to_add, value_to_add).into()
+impl Default for Empty {
+    fn default() -> Self {
+        Empty::new()
     }
 }
@@ -0,0 +1,291 @@
+use crate::accumulator::*;
+use crate::bench::*;
+use crate::mpt::*;
+use crate::node_generic::*;
+use crate::structs::*;
+use crate::util::*;
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use talk::crypto::primitives::hash::{hash, Hash};
+
+/*
+ * Log of the roots published by a broker, one RootRecord per sealed batch. The log is
+ * committed in turn: the Hash of every RootRecord is appended to a MerkleAccumulator, so
+ * that a client holding the Hash of the log can verify that an old root was really
+ * published, through get_entry_root_hash, and that a later log extends the one it holds.
+ * The MerkleTrees sealed through seal_snapshot are kept as well, and can still be proven
+ * until a RetentionPolicy drops them through prune. Every snapshot is a full copy of its
+ * MerkleTree: snapshots share no node, so they hold as much memory as separate MerkleTrees.
+ * Pruning is snapshot retention only, dropping whole snapshots: there is no node sweep yet.
+ * Sharing the unmodified nodes among the versions of a MerkleTree, and sweeping the nodes
+ * no longer reachable from a retained root, are left for later.
+ *
+ * The RootLog is not hooked into any MerkleTree: a MerkleTree knows nothing of the log, and
+ * modifying it records nothing. A root is recorded only when the broker hands the MerkleTree
+ * to seal or seal_snapshot, so every root published to clients is to be sealed first, and
+ * a root which was never sealed cannot be proven to belong to the log.
+*/
+
+/// The root of a batch, as recorded by a RootLog when the batch was sealed.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct RootRecord {
+    sequence: u64,
+    root_hash: Hash,
+    timestamp: u64,
+    leaf_count: usize,
+}
+
+/// Which of the MerkleTrees sealed through seal_snapshot are kept by RootLog::prune.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum RetentionPolicy {
+    /// The snapshots of the given number of last sealed batches.
+    KeepLast(usize),
+    /// The snapshots sealed at the given timestamp, in seconds since the UNIX epoch, or later.
+    NewerThan(u64),
+}
+
+/// The outcome of RootLog::prune.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PruneReport {
+    snapshots_removed: usize,
+    snapshots_in_use: usize,
+    bytes_reclaimed: usize,
+}
+
+pub struct RootLog<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    records: Vec<RootRecord>,
+    log: MerkleAccumulator,
+    snapshots: HashMap<u64, Arc<MerkleTree<K, V>>>,
+}
+
+impl RootRecord {
+    /// Returns a new RootRecord.
+    pub fn new(sequence: u64, root_hash: Hash, timestamp: u64, leaf_count: usize) -> Self {
+        RootRecord {
+            sequence,
+            root_hash,
+            timestamp,
+            leaf_count,
+        }
+    }
+
+    /// Returns the sequence number of the batch, starting from 0.
+    pub fn get_sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Returns the Hash of the root of the batch.
+    pub fn get_root_hash(&self) -> &Hash {
+        &self.root_hash
+    }
+
+    /// Returns the time the batch was sealed at, in seconds since the UNIX epoch.
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Returns the number of entries of the batch.
+    pub fn get_leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    /// Returns the Hash of the RootRecord, appended to the MerkleAccumulator of a RootLog.
+    pub fn get_hash(&self) -> Hash {
+        hash(self).unwrap()
+    }
+}
+
+impl RetentionPolicy {
+    /// Returns true if the snapshot of the batch of the given RootRecord is kept,
+    /// given the number of batches sealed so far.
+    pub fn retains(&self, record: &RootRecord, sealed: u64) -> bool {
+        match self {
+            RetentionPolicy::KeepLast(count) => record.sequence + *count as u64 >= sealed,
+            RetentionPolicy::NewerThan(timestamp) => record.timestamp >= *timestamp,
+        }
+    }
+}
+
+impl PruneReport {
+    /// Returns the number of snapshots dropped by the RootLog.
+    pub fn get_snapshots_removed(&self) -> usize {
+        self.snapshots_removed
+    }
+
+    /// Returns the number of dropped snapshots still held through get_snapshot: their
+    /// memory is reclaimed once the last holder drops them, and is not counted.
+    pub fn get_snapshots_in_use(&self) -> usize {
+        self.snapshots_in_use
+    }
+
+    /// Returns an estimate, in bytes, of the memory reclaimed by dropping the nodes of the
+    /// snapshots no longer referenced, as computed by estimate_memory. It is a lower bound:
+    /// the memory owned by keys and values on the heap is not taken into account.
+    pub fn get_bytes_reclaimed(&self) -> usize {
+        self.bytes_reclaimed
+    }
+}
+
+impl<K, V> RootLog<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    /// Returns a new, empty RootLog.
+    pub fn new() -> Self {
+        RootLog {
+            records: Vec::new(),
+            log: MerkleAccumulator::new(),
+            snapshots: HashMap::new(),
+        }
+    }
+
+    /// Returns the RootRecord of the given MerkleTree, sealed as the next batch.
+    /// Computes the hashes of the MerkleTree and records its root in the RootLog.
+    /// This is the only way a root enters the RootLog, together with seal_snapshot.
+    pub fn seal(&mut self, mpt: &mut MerkleTree<K, V>) -> &RootRecord {
+        let root_hash = mpt.compute_hashes();
+        self.record(root_hash, mpt.len())
+    }
+
+    /// Returns the RootRecord of the given MerkleTree, sealed as the next batch as seal does.
+    /// The MerkleTree is kept whole as a snapshot, so that it can be proven later on.
+    pub fn seal_snapshot(&mut self, mut mpt: MerkleTree<K, V>) -> &RootRecord {
+        let root_hash = mpt.compute_hashes();
+        let sequence = self.records.len() as u64;
+        let leaf_count = mpt.len();
+        self.snapshots.insert(sequence, Arc::new(mpt));
+        self.record(root_hash, leaf_count)
+    }
+
+    /// Returns the RootRecord of the batch with the given sequence number, if any.
+    pub fn get_record(&self, sequence: u64) -> Option<&RootRecord> {
+        self.records.get(sequence as usize)
+    }
+
+    /// Returns a reference to the RootRecords, in the order the batches were sealed.
+    pub fn get_records(&self) -> &Vec<RootRecord> {
+        &self.records
+    }
+
+    /// Returns the MerkleTree sealed as the batch with the given sequence number,
+    /// or None if the batch was not sealed through seal_snapshot.
+    pub fn get_snapshot(&self, sequence: u64) -> Option<Arc<MerkleTree<K, V>>> {
+        self.snapshots.get(&sequence).cloned()
+    }
+
+    /// Returns the number of sealed batches.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Returns true if no batch has been sealed yet.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Returns the Hash of the MerkleAccumulator committing to every RootRecord.
+    pub fn get_log_hash(&self) -> Hash {
+        self.log.get_hash()
+    }
+
+    /// Returns the Hash the log had when the given number of batches were sealed.
+    /// Panics if the given number exceeds the number of sealed batches.
+    pub fn get_log_hash_at(&self, len: usize) -> Hash {
+        self.log.get_hash_at(len as u64)
+    }
+
+    /// Returns a Proof for the specific given key, against the root of the batch with the
+    /// given sequence number, or None if the batch was not sealed through seal_snapshot.
+    /// The Proof contains an empty vector of Siblings if the key is not contained.
+    pub fn prove_at<Q>(&self, sequence: u64, key: &Q) -> Option<Proof>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        let mpt = self.snapshots.get(&sequence)?;
+        let key = match mpt.get_node(key) {
+            Ok(NodeGeneric::Leaf(n)) => n.get_key().clone(),
+            _ => return Some(Proof::new(Vec::new())),
+        };
+        match mpt.try_prove(key) {
+            Ok(proof) => Some(proof),
+            Err(StaleHashes) => unreachable!("the Hashes of a snapshot are computed when it is sealed"),
+        }
+    }
+
+    /// Returns a Proof that the RootRecord with the given sequence number belongs to the
+    /// RootLog, against the Hash returned by get_log_hash, or None if the batch has not been
+    /// sealed. It is verified by get_entry_root_hash, the Hash of the RootRecord being the root
+    /// of the entry and the number of sealed batches the size.
+    pub fn prove_record(&self, sequence: u64) -> Option<Proof> {
+        self.log.prove(sequence)
+    }
+
+    /// Returns a ConsistencyProof that the log, as it was when the given number of batches
+    /// were sealed, is a prefix of the current one.
+    /// Panics if the given number exceeds the number of sealed batches.
+    pub fn prove_consistency(&self, old_len: usize) -> ConsistencyProof {
+        self.log.prove_consistency(old_len as u64)
+    }
+
+    /// Returns a PruneReport, after dropping every snapshot not kept by the given
+    /// RetentionPolicy. The RootRecords and the log committing to them are kept whole.
+    /// The nodes of a snapshot are released when its last reference is dropped: whole
+    /// snapshots are retained or dropped, and no node is swept on its own.
+    pub fn prune(&mut self, policy: &RetentionPolicy) -> PruneReport {
+        let sealed = self.records.len() as u64;
+        let records = &self.records;
+        let expired: Vec<u64> = self
+            .snapshots
+            .keys()
+            .filter(|sequence| !policy.retains(&records[**sequence as usize], sealed))
+            .copied()
+            .collect();
+
+        let mut report = PruneReport {
+            snapshots_removed: 0,
+            snapshots_in_use: 0,
+            bytes_reclaimed: 0,
+        };
+        for sequence in expired {
+            let snapshot = self.snapshots.remove(&sequence).unwrap();
+            report.snapshots_removed += 1;
+            match Arc::try_unwrap(snapshot) {
+                Ok(mpt) => report.bytes_reclaimed += estimate_memory(mpt.get_root()),
+                Err(_) => report.snapshots_in_use += 1,
+            }
+        }
+        report
+    }
+
+    /// Returns the newly appended RootRecord, after committing it to the log.
+    fn record(&mut self, root_hash: Hash, leaf_count: usize) -> &RootRecord {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let sequence = self.records.len() as u64;
+        let record = RootRecord::new(sequence, root_hash, timestamp, leaf_count);
+
+        self.log.append(record.get_hash());
+        self.records.push(record);
+        self.records.last().unwrap()
+    }
+}
+
+impl<K, V> Default for RootLog<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    fn default() -> Self {
+        RootLog::new()
+    }
+}
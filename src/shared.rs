@@ -0,0 +1,64 @@
+use crate::mpt::*;
+use crate::structs::*;
+use crate::util::*;
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use talk::crypto::primitives::hash::Hash;
+
+/*
+ * A MerkleTree shared among threads. Readers generate Proofs against a frozen snapshot,
+ * while a single writer prepares the next MerkleTree on its own and publishes it once sealed.
+ * A SharedMerkleTree is Sync as long as both keys and values are Send and Sync.
+*/
+
+pub struct SharedMerkleTree<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    current: RwLock<(Arc<MerkleTree<K, V>>, Hash)>,
+}
+
+impl<K, V> SharedMerkleTree<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    /// Returns a new SharedMerkleTree whose first snapshot is the given MerkleTree.
+    /// Computes the hashes of the given MerkleTree before freezing it.
+    pub fn new(mut mpt: MerkleTree<K, V>) -> Self {
+        let root_hash = mpt.compute_hashes();
+        SharedMerkleTree {
+            current: RwLock::new((Arc::new(mpt), root_hash)),
+        }
+    }
+
+    /// Returns the current snapshot of the MerkleTree. The snapshot stays valid
+    /// even after a new MerkleTree has been published.
+    pub fn snapshot(&self) -> Arc<MerkleTree<K, V>> {
+        self.current.read().unwrap().0.clone()
+    }
+
+    /// Returns the Hash of the root of the current snapshot.
+    pub fn root_hash(&self) -> Hash {
+        self.current.read().unwrap().1
+    }
+
+    /// Returns a Proof for the specific given key, generated against the current snapshot.
+    /// The Proof contains an empty vector of Siblings if the key is not contained.
+    pub fn prove(&self, key: K) -> Proof {
+        match self.snapshot().try_prove(key) {
+            Ok(proof) => proof,
+            Err(StaleHashes) => unreachable!("the Hashes of a snapshot are computed before it is published"),
+        }
+    }
+
+    /// Returns the Hash of the root of the given MerkleTree. Computes the hashes of the given
+    /// MerkleTree and publishes it as the new snapshot. Readers holding a previous snapshot 
+    /// are not affected.
+    pub fn publish(&self, mut mpt: MerkleTree<K, V>) -> Hash {
+        let root_hash = mpt.compute_hashes();
+        *self.current.write().unwrap() = (Arc::new(mpt), root_hash);
+        root_hash
+    }
+}
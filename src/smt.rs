@@ -0,0 +1,372 @@
+use crate::mpt::*;
+use crate::node_generic::*;
+use crate::structs::*;
+use crate::util::*;
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use talk::crypto::primitives::hash::Hash;
+
+/*
+ * Fixed-depth sparse Merkle tree mode. Every key sits at depth 256, at the end of its path,
+ * and every subtree holding no key has the default Hash of its height: the Hash of an Empty
+ * node at height 0, and the Hash of an Internal node whose two children have the default
+ * Hash of the height below otherwise. The entries are stored in the variable-depth trie of
+ * a MerkleTree, whose Leaves are extended down to depth 256 when hashing, so that the Hash
+ * of the root is the one of the full tree of depth 256 without ever building it.
+ * A SparseProof only carries the Siblings which differ from the default Hashes, together
+ * with a bitmap telling which ones they are.
+*/
+
+const DEPTH: usize = 256;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SparseMerkleTree<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    tree: MerkleTree<K, V>,
+    // Hashes of the Internal nodes of the trie, by depth and path prefix (the bits below the
+    // depth are set to 0), discarded along the path of every key inserted or removed
+    #[serde(skip)]
+    hashes: HashMap<(u8, [u8; 32]), Hash>,
+}
+
+/// A Proof issued by a SparseMerkleTree, proving either the Leaf of a key or its absence.
+/// Bit i of the bitmap is set if the Sibling met at depth i + 1 does not have the default Hash
+/// of its height. The non-default Siblings go from the deepest one to the one of the root.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SparseProof {
+    key_path: [u8; 32],
+    bitmap: [u8; 32],
+    siblings: Vec<Hash>,
+}
+
+/// Returns the default Hashes of the empty subtrees of a SparseMerkleTree, indexed by height
+/// from 0 (a single Empty Leaf) to 256 (the root of an empty SparseMerkleTree).
+pub fn get_default_hashes() -> &'static [Hash] {
+    static DEFAULT_HASHES: OnceLock<Vec<Hash>> = OnceLock::new();
+    DEFAULT_HASHES.get_or_init(|| {
+        let mut defaults = vec![Empty::get_hash()];
+        for height in 0..DEPTH {
+            defaults.push(Internal::<(), ()>::create_hash(defaults[height], defaults[height]));
+        }
+        defaults
+    })
+}
+
+/// Returns the Hash of the subtree, at the given depth, holding the single Leaf whose Hash
+/// and path are given. The Hash of the Leaf is folded with the default Hashes up to the depth.
+pub fn get_single_leaf_hash(leaf_hash: Hash, key_path: &[u8; 32], depth: usize) -> Hash {
+    let defaults = get_default_hashes();
+    let mut hash_final = leaf_hash;
+    for index in (depth..DEPTH).rev() {
+        let sibling = defaults[DEPTH - 1 - index];
+        hash_final = if get_bit_direction(key_path, index as u8) {
+            Internal::<(), ()>::create_hash(sibling, hash_final)
+        } else {
+            Internal::<(), ()>::create_hash(hash_final, sibling)
+        };
+    }
+    hash_final
+}
+
+impl<K, V> SparseMerkleTree<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    /// Returns a new SparseMerkleTree.
+    pub fn new() -> SparseMerkleTree<K, V> {
+        SparseMerkleTree {
+            tree: MerkleTree::new(),
+            hashes: HashMap::new(),
+        }
+    }
+
+    /// Returns the variable-depth MerkleTree storing the entries of the SparseMerkleTree.
+    pub fn get_tree(&self) -> &MerkleTree<K, V> {
+        &self.tree
+    }
+
+    /// Returns the value previously associated to the given key, or None if the key was
+    /// not contained. Inserts a new Leaf in the SparseMerkleTree if the key is not
+    /// contained or substitutes the current value associated to the given key.
+    /// Panics if there is a collision
+    pub fn insert(&mut self, key_to_add: K, value_to_add: V) -> Option<V> {
+        self.discard_hashes(&key_to_add.key_path());
+        self.tree.insert(key_to_add, value_to_add)
+    }
+
+    /// Returns a Result which contains: the value previously associated to the given key, or
+    /// None if the key was not contained; Err(Collision), leaving the SparseMerkleTree untouched,
+    /// if the given key collides with another key. Behaves as insert otherwise.
+    pub fn try_insert(&mut self, key_to_add: K, value_to_add: V) -> Result<Option<V>, Collision> {
+        self.discard_hashes(&key_to_add.key_path());
+        self.tree.try_insert(key_to_add, value_to_add)
+    }
+
+    /// Returns the value associated to the given key, or None if the key is not contained.
+    /// Removes the Leaf of the key from the SparseMerkleTree.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.discard_hashes(&key.key_path());
+        self.tree.remove(key)
+    }
+
+    /// Returns a reference of the value associated to the given key,
+    /// or None if the key is not contained.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.tree.get(key)
+    }
+
+    /// Returns true if the given key is contained in the SparseMerkleTree.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.tree.contains_key(key)
+    }
+
+    /// Returns the number of entries of the SparseMerkleTree.
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Returns true if the SparseMerkleTree holds no entry.
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Returns the Hash of the root of the SparseMerkleTree, as a tree of depth 256.
+    /// Computes the Hashes of the Internal nodes discarded since the last call.
+    pub fn compute_hashes(&mut self) -> Hash {
+        let mut hashes = std::mem::take(&mut self.hashes);
+        let root_hash = compute_hash(self.tree.get_root(), 0, &mut [0u8; 32], &mut hashes);
+        self.hashes = hashes;
+        root_hash
+    }
+
+    /// Returns a SparseProof of the Leaf of the given key or, if the key is not contained,
+    /// of its absence. Only the Hashes discarded since the last call to compute_hashes
+    /// are computed again, as compute_hashes does.
+    pub fn prove<Q>(&mut self, key: &Q) -> SparseProof
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        self.compute_hashes();
+        match self.try_prove(key) {
+            Ok(proof) => proof,
+            Err(StaleHashes) => unreachable!("the Hashes have just been computed"),
+        }
+    }
+
+    /// Returns a Result which contains: a SparseProof for the given key, as prove does;
+    /// Err(StaleHashes) if the Hash of an Internal node needed by the SparseProof has been
+    /// discarded by a modification since the last call to compute_hashes.
+    pub fn try_prove<Q>(&self, key: &Q) -> Result<SparseProof, StaleHashes>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + KeyPath + Eq,
+    {
+        let key_path = key.key_path();
+        let mut siblings = Vec::<(usize, Hash)>::new();
+        let mut prefix = [0u8; 32];
+        let mut node = self.tree.get_root();
+        let mut depth = 0;
+
+        while let NodeGeneric::Internal(n) = node {
+            let direction = get_bit_direction(&key_path, depth as u8);
+            let (next, other) = if direction {
+                (n.get_right(), n.get_left())
+            } else {
+                (n.get_left(), n.get_right())
+            };
+            set_bit(&mut prefix, depth, !direction);
+            siblings.push((depth, self.get_cached_hash(other, depth + 1, &prefix)?));
+            set_bit(&mut prefix, depth, direction);
+            node = next;
+            depth += 1;
+        }
+
+        // the only Leaf which may lie below is one of another key, found in the subtree of
+        // the Sibling met at the depth where the two paths part
+        if let NodeGeneric::Leaf(n) = node {
+            let other_path = n.get_key().key_path();
+            let shared = shared_bits(&other_path, &key_path);
+            if shared < DEPTH {
+                siblings.push((shared, get_single_leaf_hash(n.get_hash(), &other_path, shared + 1)));
+            }
+        }
+
+        let defaults = get_default_hashes();
+        let mut bitmap = [0u8; 32];
+        let mut non_default = Vec::<Hash>::new();
+        for (index, sibling) in siblings.into_iter().rev() {
+            if sibling != defaults[DEPTH - 1 - index] {
+                set_bit(&mut bitmap, index, true);
+                non_default.push(sibling);
+            }
+        }
+        Ok(SparseProof::new(key_path, bitmap, non_default))
+    }
+
+    /// Returns the Hash of the given node of the trie, at the given depth and path prefix,
+    /// as a subtree of the tree of depth 256, or Err(StaleHashes) if the Hash of an Internal
+    /// node has not been computed.
+    fn get_cached_hash(&self, node: &NodeGeneric<K, V>, depth: usize, prefix: &[u8; 32]) -> Result<Hash, StaleHashes> {
+        match node {
+            NodeGeneric::Internal(_) => self.hashes.get(&(depth as u8, *prefix)).copied().ok_or(StaleHashes),
+            NodeGeneric::Leaf(n) => Ok(get_single_leaf_hash(n.get_hash(), &n.get_key().key_path(), depth)),
+            NodeGeneric::Empty(_) => Ok(get_default_hashes()[DEPTH - depth]),
+        }
+    }
+
+    /// Discards the cached Hashes of every Internal node along the given path.
+    fn discard_hashes(&mut self, key_path: &[u8; 32]) {
+        let mut prefix = [0u8; 32];
+        for depth in 0..DEPTH {
+            self.hashes.remove(&(depth as u8, prefix));
+            set_bit(&mut prefix, depth, get_bit_direction(key_path, depth as u8));
+        }
+    }
+}
+
+impl<K, V> Default for SparseMerkleTree<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    fn default() -> Self {
+        SparseMerkleTree::new()
+    }
+}
+
+/// Every SparseMerkleTree has the same depth, so two of them are equal if they hold the same
+/// Leaves. The cache of the Hashes is left out.
+impl<K, V> PartialEq for SparseMerkleTree<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone + PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        let mut leaves = Vec::<Leaf<K, V>>::new();
+        let mut other_leaves = Vec::<Leaf<K, V>>::new();
+        self.tree.get_root().get_leaves(&mut leaves);
+        other.tree.get_root().get_leaves(&mut other_leaves);
+        leaves == other_leaves
+    }
+}
+
+/// Returns the Hash of the given node of the trie, at the given depth and path prefix, as a
+/// subtree of the tree of depth 256. The bits of the prefix below the depth must be set to 0.
+/// The Hashes of Internal nodes are read from, or stored into, the given cache.
+fn compute_hash<K, V>(
+    node: &NodeGeneric<K, V>,
+    depth: usize,
+    prefix: &mut [u8; 32],
+    hashes: &mut HashMap<(u8, [u8; 32]), Hash>,
+) -> Hash
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    match node {
+        NodeGeneric::Internal(n) => {
+            let cache_key = (depth as u8, *prefix);
+            if let Some(h) = hashes.get(&cache_key) {
+                return *h;
+            }
+            set_bit(prefix, depth, false);
+            let l_hash = compute_hash(n.get_left(), depth + 1, prefix, hashes);
+            set_bit(prefix, depth, true);
+            let r_hash = compute_hash(n.get_right(), depth + 1, prefix, hashes);
+            set_bit(prefix, depth, false);
+            let this_hash = Internal::<K, V>::create_hash(l_hash, r_hash);
+            hashes.insert(cache_key, this_hash);
+            this_hash
+        }
+        NodeGeneric::Leaf(n) => get_single_leaf_hash(n.get_hash(), &n.get_key().key_path(), depth),
+        NodeGeneric::Empty(_) => get_default_hashes()[DEPTH - depth],
+    }
+}
+
+/// Sets the bit of the given index of the given array of u8 (true <--> 1, false <--> 0).
+fn set_bit(arr: &mut [u8; 32], index: usize, bit: bool) {
+    let sub_index: u8 = 1 << (7 - (index % 8));
+    if bit {
+        arr[index / 8] |= sub_index;
+    } else {
+        arr[index / 8] &= !sub_index;
+    }
+}
+
+impl SparseProof {
+    /// Returns a new SparseProof.
+    pub fn new(key_path: [u8; 32], bitmap: [u8; 32], siblings: Vec<Hash>) -> Self {
+        SparseProof {
+            key_path,
+            bitmap,
+            siblings,
+        }
+    }
+
+    /// Returns the path of the proven key.
+    pub fn get_key_path(&self) -> &[u8; 32] {
+        &self.key_path
+    }
+
+    /// Returns the bitmap of the non-default Siblings.
+    pub fn get_bitmap(&self) -> &[u8; 32] {
+        &self.bitmap
+    }
+
+    /// Returns a reference to the non-default Siblings, from the deepest one to the one of the root.
+    pub fn get_siblings(&self) -> &Vec<Hash> {
+        &self.siblings
+    }
+}
+
+impl MerkleProof for SparseProof {
+    fn get_root_hash_from(&self, leaf_hash: Hash) -> Result<Hash, ()> {
+        let defaults = get_default_hashes();
+        let mut siblings = self.siblings.iter();
+        let mut hash_final = leaf_hash;
+
+        for index in (0..DEPTH).rev() {
+            let sibling = if get_bit_direction(&self.bitmap, index as u8) {
+                *siblings.next().ok_or(())?
+            } else {
+                defaults[DEPTH - 1 - index]
+            };
+            hash_final = if get_bit_direction(&self.key_path, index as u8) {
+                Internal::<(), ()>::create_hash(sibling, hash_final)
+            } else {
+                Internal::<(), ()>::create_hash(hash_final, sibling)
+            };
+        }
+        match siblings.next() {
+            Some(_) => Err(()),
+            None => Ok(hash_final),
+        }
+    }
+
+    fn follows_path(&self, key_path: &[u8; 32]) -> bool {
+        self.key_path == *key_path
+    }
+
+    fn get_hash_count(&self) -> usize {
+        self.siblings.len()
+    }
+}
@@ -1,8 +1,10 @@
+use crate::node_generic::*;
+use crate::util::*;
 use serde::{Deserialize, Serialize};
 use talk::crypto::primitives::hash::Hash;
 
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum Direction {
     Left,
     Right,
@@ -10,13 +12,13 @@ pub enum Direction {
 
 
 impl From<Left> for Direction {
-    fn from(left: Left) -> Self {
+    fn from(_: Left) -> Self {
         Direction::Left
     }
 }
 
 impl From<Right> for Direction {
-    fn from(left: Right) -> Self {
+    fn from(_: Right) -> Self {
         Direction::Right
     }
 }
@@ -26,7 +28,7 @@ pub struct Left {}
 
 impl Left {
     /// Returns the boolean associated with Left: false.
-    fn get_val() -> bool {
+    pub fn get_val() -> bool {
         false
     }
 }
@@ -37,7 +39,7 @@ pub struct Right {}
 
 impl Right {
     /// Returns the boolean associated with Right: true.
-    fn get_val() -> bool {
+    pub fn get_val() -> bool {
         true
     }
 }
@@ -66,7 +68,7 @@ where
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Sibling {
     hash: Hash,
     direction: Direction,
@@ -93,7 +95,7 @@ impl Sibling {
 }
 
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Proof {
     siblings: Vec<Sibling>,
 }
@@ -109,3 +111,241 @@ impl Proof {
         &self.siblings
     }
 }
+
+/// A Proof carrying the opening of the proven Leaf, that is its key, its value and its salt
+/// if the Leaf is salted, so that it can be verified on its own once forwarded or stored.
+/// The Hash of the root it claims to be issued against, if given, is not signed: it is only
+/// checked for consistency by verify, and must be compared with a trusted root by the caller.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct OpenedProof<K, V> {
+    key: K,
+    value: V,
+    salt: Option<Hash>,
+    siblings: Vec<Sibling>,
+    claimed_root: Option<Hash>,
+}
+
+impl<K, V> OpenedProof<K, V> {
+    /// Returns a new OpenedProof.
+    pub fn new(key: K, value: V, salt: Option<Hash>, siblings: Vec<Sibling>, claimed_root: Option<Hash>) -> Self {
+        OpenedProof {
+            key,
+            value,
+            salt,
+            siblings,
+            claimed_root,
+        }
+    }
+
+    /// Returns a reference to the key of the proven Leaf.
+    pub fn get_key(&self) -> &K {
+        &self.key
+    }
+
+    /// Returns a reference to the value of the proven Leaf.
+    pub fn get_value(&self) -> &V {
+        &self.value
+    }
+
+    /// Returns a reference to the salt of the proven Leaf, if the Leaf is salted.
+    pub fn get_salt(&self) -> Option<&Hash> {
+        self.salt.as_ref()
+    }
+
+    /// Returns a reference to the vector of Siblings, from the deepest one to the one of the root.
+    pub fn get_siblings(&self) -> &Vec<Sibling> {
+        &self.siblings
+    }
+
+    /// Returns a reference to the Hash of the root the OpenedProof claims to be issued
+    /// against, if given. The claim is not authenticated.
+    pub fn get_claimed_root(&self) -> Option<&Hash> {
+        self.claimed_root.as_ref()
+    }
+}
+
+/// A proof of membership of a single Leaf, whatever the number of children of the nodes
+/// of the tree which issued it.
+pub trait MerkleProof {
+    /// Returns a Result which contains: the Hash of the root, computed by hashing the given
+    /// Hash of the proven Leaf along the proof; Err(()) if the proof is malformed.
+    fn get_root_hash_from(&self, leaf_hash: Hash) -> Result<Hash, ()>;
+
+    /// Returns true if the positions taken along the proof are the ones given by the
+    /// given path of a key, so that the proof cannot be replayed for another position.
+    fn follows_path(&self, key_path: &[u8; 32]) -> bool;
+
+    /// Returns the number of Hashes carried by the proof.
+    fn get_hash_count(&self) -> usize;
+}
+
+
+
+/// A Proof for every entry whose key hash starts with a given prefix of bits
+/// (true <--> Right, false <--> Left). The Leaves are the ones below the deepest node 
+/// reached by following the prefix, and the Siblings lead from that node to the root. 
+/// If the path ends before the prefix is exhausted, at most one Leaf is given, which may
+/// lie outside of the prefix and only witnesses that the range is empty.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RangeProof<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    prefix: Vec<bool>,
+    leaves: Vec<RangeLeaf<K, V>>,
+    siblings: Vec<Sibling>,
+}
+
+/// A Leaf, as given by a RangeProof. The salt of a salted Leaf is never disclosed:
+/// only the path of its key and its Hash are given, so that its entry stays hidden.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum RangeLeaf<K, V> {
+    /// An unsalted Leaf, whose Hash is recomputed from its key and its value.
+    Opened { key: K, value: V },
+    /// A salted Leaf, given by the path of its key and its Hash only.
+    Sealed { key_path: [u8; 32], leaf_hash: Hash },
+}
+
+impl<K, V> RangeProof<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    /// Returns a new RangeProof.
+    pub fn new(p: Vec<bool>, l: Vec<RangeLeaf<K, V>>, s: Vec<Sibling>) -> RangeProof<K, V> {
+        RangeProof {
+            prefix: p,
+            leaves: l,
+            siblings: s,
+        }
+    }
+
+    /// Returns a reference to the prefix proven by the RangeProof invoking the method.
+    pub fn get_prefix(&self) -> &Vec<bool> {
+        &self.prefix
+    }
+
+    /// Returns a reference to the vector of Leaves associated with the RangeProof invoking the method.
+    pub fn get_leaves(&self) -> &Vec<RangeLeaf<K, V>> {
+        &self.leaves
+    }
+
+    /// Returns a reference to the vector of Siblings associated with the RangeProof invoking the method.
+    pub fn get_siblings(&self) -> &Vec<Sibling> {
+        &self.siblings
+    }
+
+    /// Returns the Leaves whose key hash starts with the prefix proven by the RangeProof.
+    pub fn get_leaves_in_range(&self) -> Vec<&RangeLeaf<K, V>> {
+        self.leaves
+            .iter()
+            .filter(|leaf| {
+                starts_with_prefix(&leaf.get_key_path(), &self.prefix)
+            })
+            .collect()
+    }
+}
+
+impl<K, V> RangeLeaf<K, V>
+where
+    K: Serialize + KeyPath + Clone + Eq,
+    V: Serialize + Clone,
+{
+    /// Returns the RangeLeaf of the given Leaf, sealed if the Leaf is salted.
+    pub fn from_leaf(leaf: &Leaf<K, V>) -> Self {
+        match leaf.get_salt() {
+            Some(_) => RangeLeaf::Sealed {
+                key_path: leaf.get_key().key_path(),
+                leaf_hash: leaf.get_hash(),
+            },
+            None => RangeLeaf::Opened {
+                key: leaf.get_key().clone(),
+                value: leaf.get_value().clone(),
+            },
+        }
+    }
+
+    /// Returns a reference to the key of the RangeLeaf, or None if it is sealed.
+    pub fn get_key(&self) -> Option<&K> {
+        match self {
+            RangeLeaf::Opened { key, .. } => Some(key),
+            RangeLeaf::Sealed { .. } => None,
+        }
+    }
+
+    /// Returns a reference to the value of the RangeLeaf, or None if it is sealed.
+    pub fn get_value(&self) -> Option<&V> {
+        match self {
+            RangeLeaf::Opened { value, .. } => Some(value),
+            RangeLeaf::Sealed { .. } => None,
+        }
+    }
+
+    /// Returns the path of the key of the RangeLeaf.
+    pub fn get_key_path(&self) -> [u8; 32] {
+        match self {
+            RangeLeaf::Opened { key, .. } => key.key_path(),
+            RangeLeaf::Sealed { key_path, .. } => *key_path,
+        }
+    }
+
+    /// Returns the Hash of the Leaf, recomputed from its key and its value if it is opened.
+    pub fn get_hash(&self) -> Hash {
+        match self {
+            RangeLeaf::Opened { key, value } => Leaf::<&K, &V>::create_leaf_hash(key, value),
+            RangeLeaf::Sealed { leaf_hash, .. } => *leaf_hash,
+        }
+    }
+}
+
+/// A difference between two MerkleTrees, telling how an entry of the first one
+/// has to change to match the second one.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum Change<K, V> {
+    /// The key is only contained in the second MerkleTree.
+    Added { key: K, value: V },
+    /// The key is only contained in the first MerkleTree.
+    Removed { key: K, value: V },
+    /// The key is contained in both MerkleTrees, but the Leaves differ.
+    Changed { key: K, old: V, new: V },
+}
+
+/// A breach of the canonical shape of a MerkleTree. Each variant carries the path
+/// (true <--> Right, false <--> Left) leading from the root to the offending node.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum Violation {
+    /// The root of the MerkleTree is not an Internal node.
+    RootNotInternal,
+    /// An Internal node, other than the root, with less than two Leaves in its subtree.
+    NonMinimalInternal(Vec<bool>),
+    /// An Internal node whose cached Hash differs from the Hash of its children.
+    StaleHash(Vec<bool>),
+    /// A Leaf whose key does not follow the path leading to it.
+    MisplacedLeaf(Vec<bool>),
+}
+
+/// Two different keys whose hashes share the same path down to the deepest level of a 
+/// MerkleTree (the first 255 bits), so that they cannot be told apart by their path.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Collision;
+
+/// A Proof requested while the Hash of an Internal node along the way has been discarded
+/// by a modification of the MerkleTree and not computed again yet.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct StaleHashes;
+
+/// The reasons a RangeProof cannot be generated.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum RangeError {
+    /// The prefix is longer than MAX_PREFIX_LEN bits.
+    PrefixTooLong,
+    /// The Hash of an Internal node needed by the RangeProof has been discarded.
+    StaleHashes,
+}
+
+impl From<StaleHashes> for RangeError {
+    fn from(_: StaleHashes) -> Self {
+        RangeError::StaleHashes
+    }
+}
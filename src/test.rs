@@ -1,10 +1,14 @@
 use crate::client_verify::*;
-use crate::{mpt::*, node_generic::*, structs::*};
+use crate::{accumulator::*, anti_entropy::*, arena::*, bench::*, commitment::*, entry::*, hexary::*, journal::*, mpt::*, node_generic::*, root_log::*, shared::*, smt::*, structs::*};
 use talk::crypto::primitives::hash::hash;
 use talk::crypto::primitives::hash::Hash;
+use crate::util::*;
+use serde::Serialize;
 
 
 #[cfg(test)]
+#[allow(non_snake_case, unused_variables, unused_mut)]
+#[allow(clippy::assertions_on_constants, clippy::get_first)]
 mod tests {
     use super::*;
 
@@ -317,7 +321,7 @@ mod tests {
         assert_eq!(proof_nothing.get_siblings().len(), 0);
 
         let mut proof = mpt.prove("GGGGG");
-        let reconstructed_hash_root = get_root_hash(proof, 8u8, Id::new("GGGGG"));
+        let reconstructed_hash_root = get_root_hash(proof, 8u8, Id::new("GGGGG")).unwrap();
 
         match mpt.get_root() {
             NodeGeneric::Internal(n) => {
@@ -329,4 +333,1293 @@ mod tests {
             _ => assert!(false),
         }
     }
+
+    #[test]
+    fn insertion_order_independence() {
+        let keys = ["HHHHH", "AAAAA", "BBBBB", "CCCCC", "DDDDD", "EEEEE", "FFFFF", "GGGGG"];
+        let mut mpt1: MerkleTree<&str, u8> = MerkleTree::new();
+        let mut mpt2: MerkleTree<&str, u8> = MerkleTree::new();
+        for (i, key) in keys.iter().enumerate() {
+            mpt1.insert(key, i as u8);
+        }
+        for (i, key) in keys.iter().enumerate().rev() {
+            mpt2.insert(key, i as u8);
+        }
+
+        assert!(mpt1.is_canonical());
+        assert!(mpt2.is_canonical());
+        assert_eq!(mpt1.compute_hashes(), mpt2.compute_hashes());
+        assert_eq!(mpt1, mpt2);
+    }
+
+    #[test]
+    fn canonicalize_hand_built() {
+        //"Hello" starts with bit 0, so it belongs to the left child of the root
+        let chain: NodeGeneric<&str, u8> = Internal::new(
+            Empty::new().into(),
+            Internal::new(Empty::new().into(), Empty::new().into(), None).into(),
+            None,
+        )
+        .into();
+        let root = Internal::new(chain, Leaf::new("Hello", 1).into(), Some(Empty::get_hash()));
+        let mut mpt: MerkleTree<&str, u8> = MerkleTree::new();
+        *mpt.get_mut_root() = root.into();
+
+        let violations = mpt.find_violations();
+        assert!(violations.contains(&Violation::NonMinimalInternal(vec![false])));
+        assert!(violations.contains(&Violation::NonMinimalInternal(vec![false, true])));
+        assert!(violations.contains(&Violation::MisplacedLeaf(vec![true])));
+        assert!(violations.contains(&Violation::StaleHash(vec![])));
+
+        mpt.canonicalize();
+        let mut expected: MerkleTree<&str, u8> = MerkleTree::new();
+        expected.insert("Hello", 1);
+        assert!(mpt.is_canonical());
+        assert_eq!(mpt, expected);
+    }
+
+    #[test]
+    fn shared_merkle_tree_concurrent_prove() {
+        let keys = ["HHHHH", "AAAAA", "BBBBB", "CCCCC", "DDDDD", "EEEEE", "FFFFF", "GGGGG"];
+        let mut mpt: MerkleTree<&str, u8> = MerkleTree::new();
+        for (i, key) in keys.iter().enumerate() {
+            mpt.insert(key, i as u8);
+        }
+        let shared = SharedMerkleTree::new(mpt.clone());
+
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let snapshot = shared.snapshot();
+                    let root_hash = snapshot.get_root().get_hash();
+                    for (i, key) in keys.iter().enumerate() {
+                        let proof = snapshot.try_prove(key).unwrap();
+                        assert_eq!(get_root_hash(proof, i as u8, Id::new(*key)), Ok(root_hash));
+                    }
+                });
+            }
+            //the writer prepares and publishes the next batch meanwhile
+            let mut next = mpt.clone();
+            next.insert("Hello", 42);
+            shared.publish(next);
+        });
+
+        let proof = shared.prove("Hello");
+        assert_eq!(get_root_hash(proof, 42u8, Id::new("Hello")), Ok(shared.root_hash()));
+    }
+
+    #[test]
+    fn compute_hashes_parallel_matches_serial() {
+        let mut mpt: MerkleTree<u32, u32> = MerkleTree::new();
+        for i in 0..2000u32 {
+            mpt.insert(i, i * 7);
+        }
+        let mut serial = mpt.clone();
+        let serial_hash = serial.compute_hashes();
+
+        for split_depth in [0u8, 1, 3, 8].iter() {
+            let mut parallel = mpt.clone();
+            assert_eq!(parallel.compute_hashes_parallel(*split_depth), serial_hash);
+            assert_eq!(parallel, serial);
+        }
+    }
+
+    #[test]
+    fn committed_merkle_tree_same_root() {
+        let payloads = [("AAAAA", vec![1u8, 2, 3]), ("BBBBB", vec![4u8; 100]), ("CCCCC", vec![])];
+        let mut mpt: MerkleTree<&str, Vec<u8>> = MerkleTree::new();
+        let mut committed: CommittedMerkleTree<&str> = CommittedMerkleTree::new();
+        for (key, payload) in payloads.iter() {
+            mpt.insert(key, payload.clone());
+            committed.insert(key, payload);
+        }
+        //substitute a commitment
+        mpt.insert("BBBBB", vec![5u8]);
+        committed.insert("BBBBB", &vec![5u8]);
+
+        let root_hash = mpt.compute_hashes();
+        assert_eq!(committed.compute_hashes(), root_hash);
+        assert_eq!(committed.get_value_hash("CCCCC"), &hash(&Vec::<u8>::new()).unwrap());
+
+        let proof = committed.prove("BBBBB");
+        assert_eq!(get_root_hash(proof, vec![5u8], Id::new("BBBBB")), Ok(root_hash));
+    }
+
+    #[test]
+    fn committed_merkle_tree_proofs_and_sync() {
+        let mut mpt: MerkleTree<u32, Vec<u32>> = MerkleTree::new();
+        let mut committed: CommittedMerkleTree<u32> = CommittedMerkleTree::new();
+        for i in 0..60u32 {
+            mpt.insert(i, vec![i; 3]);
+            committed.insert(i, &vec![i; 3]);
+        }
+        let root_hash = mpt.compute_hashes();
+        assert_eq!(committed.compute_hashes(), root_hash);
+        assert_eq!(hash_value(&ValueHash(root_hash)), root_hash);
+
+        //opened and range proofs rebuild every Leaf from the Hash of its value
+        let opened = committed.prove_opened(&7).unwrap();
+        assert_eq!(opened.get_value(), &ValueHash(hash(&vec![7u32; 3]).unwrap()));
+        assert!(opened.verify_against(root_hash));
+        for prefix in [vec![], vec![true], vec![false, true, true]].iter() {
+            let range_proof = committed.prove_range(prefix).unwrap();
+            assert_eq!(get_range_root_hash(&range_proof), Ok(root_hash));
+        }
+
+        //a replica of the committed tree synchronizes onto the same root
+        let mut replica: MerkleTree<u32, ValueHash> = MerkleTree::new();
+        for i in 10..70u32 {
+            replica.insert(i, ValueHash(hash(&i).unwrap()));
+        }
+        let mut session = SyncSession::new();
+        let mut rounds = 0;
+        while !session.is_done() {
+            while let Some(request) = session.next_request() {
+                let response = respond(committed.get_tree(), &request).unwrap();
+                let response: SyncResponse<u32, ValueHash> =
+                    serde_json::from_str(&serde_json::to_string(&response).unwrap()).unwrap();
+                session.handle_response(&mut replica, response).unwrap();
+                rounds += 1;
+            }
+            assert!(rounds < 1000);
+        }
+        assert_eq!(replica.compute_hashes(), root_hash);
+
+        //writing a value in place refreshes its Leaf to the committed Hash
+        *replica.get_mut(&7).unwrap() = ValueHash(hash(&vec![0u32]).unwrap());
+        mpt.insert(7, vec![0u32]);
+        assert_eq!(replica.compute_hashes(), mpt.compute_hashes());
+    }
+
+    #[test]
+    fn salted_leaves_get_root_hash() {
+        let salt = hash(&"a secret only known to the owner of AAAAA").unwrap();
+        let mut mpt: MerkleTree<&str, u8> = MerkleTree::new();
+        mpt.insert("HHHHH", 1);
+        mpt.insert_salted("AAAAA", 2, salt);
+        mpt.insert("BBBBB", 3);
+        let root_hash = mpt.compute_hashes();
+
+        let unsalted = Leaf::<&str, u8>::new("AAAAA", 2);
+        match mpt.get_node("AAAAA").unwrap() {
+            NodeGeneric::Leaf(n) => assert_ne!(n.get_hash(), unsalted.get_hash()),
+            _ => assert!(false),
+        }
+        assert_eq!(mpt.get_salt("AAAAA"), Some(&salt));
+        assert_eq!(mpt.get_salt("BBBBB"), None);
+        assert_eq!(mpt.get_salt("ZZZZZ"), None);
+
+        let proof = mpt.prove("AAAAA");
+        assert_eq!(get_root_hash_salted(proof, 2u8, Id::new("AAAAA"), salt), Ok(root_hash));
+        let proof = mpt.prove("AAAAA");
+        assert_ne!(get_root_hash(proof, 2u8, Id::new("AAAAA")), Ok(root_hash));
+    }
+
+    #[test]
+    fn prove_range_complete() {
+        let mut mpt: MerkleTree<u32, u32> = MerkleTree::new();
+        for i in 0..300u32 {
+            mpt.insert(i, i + 1);
+        }
+        let root_hash = mpt.compute_hashes();
+
+        for prefix in [vec![], vec![true], vec![true, false, true], vec![false; 12]].iter() {
+            let range_proof = mpt.prove_range(prefix).unwrap();
+            assert_eq!(get_range_root_hash(&range_proof), Ok(root_hash));
+
+            let mut expected: Vec<u32> = (0..300u32)
+                .filter(|i| {
+                    let key_hash = hash(i).unwrap().to_bytes();
+                    prefix
+                        .iter()
+                        .enumerate()
+                        .all(|(j, bit)| get_bit_direction(&key_hash, j as u8) == *bit)
+                })
+                .collect();
+            let mut in_range: Vec<u32> =
+                range_proof.get_leaves_in_range().iter().filter_map(|leaf| leaf.get_key()).copied().collect();
+            expected.sort();
+            in_range.sort();
+            assert_eq!(in_range, expected);
+        }
+
+        //a broker omitting an entry of the range is caught
+        let range_proof = mpt.prove_range(&[true, false]).unwrap();
+        let mut leaves = range_proof.get_leaves().clone();
+        leaves.pop();
+        let siblings = range_proof.get_siblings().clone();
+        let truncated = RangeProof::new(vec![true, false], leaves, siblings);
+        assert_ne!(get_range_root_hash(&truncated), Ok(root_hash));
+
+        //a prefix longer than any path is rejected on both sides, instead of wrapping around
+        let too_long = vec![false; MAX_PREFIX_LEN + 45];
+        assert_eq!(mpt.prove_range(&too_long).err(), Some(RangeError::PrefixTooLong));
+        assert!(mpt.prove_range(&too_long[..MAX_PREFIX_LEN]).is_ok());
+        assert!(!starts_with_prefix(&[0u8; 32], &too_long));
+        let empty_range = RangeProof::<u32, u32>::new(too_long, vec![], vec![]);
+        assert_eq!(get_range_root_hash(&empty_range), Err(()));
+    }
+
+    #[test]
+    fn prove_range_hides_salts() {
+        let mut mpt: MerkleTree<u32, u32> = MerkleTree::new();
+        for i in 0..100u32 {
+            if i % 2 == 0 {
+                mpt.insert(i, i);
+            } else {
+                mpt.insert_salted(i, i, hash(&(i, "salt")).unwrap());
+            }
+        }
+        let root_hash = mpt.compute_hashes();
+
+        let range_proof = mpt.prove_range(&[false]).unwrap();
+        assert_eq!(get_range_root_hash(&range_proof), Ok(root_hash));
+        for leaf in range_proof.get_leaves() {
+            match leaf {
+                RangeLeaf::Opened { key, .. } => assert!(mpt.get_salt(key).is_none()),
+                RangeLeaf::Sealed { .. } => assert!(leaf.get_key().is_none()),
+            }
+        }
+
+        //a broker altering the value of an opened Leaf is caught
+        let mut leaves = range_proof.get_leaves().clone();
+        let position = leaves.iter().position(|leaf| leaf.get_key().is_some()).unwrap();
+        if let RangeLeaf::Opened { value, .. } = &mut leaves[position] {
+            *value += 1;
+        }
+        let forged = RangeProof::new(vec![false], leaves, range_proof.get_siblings().clone());
+        assert_ne!(get_range_root_hash(&forged), Ok(root_hash));
+
+        mpt.insert(1000, 1000);
+        assert!(mpt.try_prove_range(&[false]).is_err() || mpt.try_prove_range(&[true]).is_err());
+        mpt.compute_hashes();
+        assert!(mpt.try_prove_range(&[false]).is_ok());
+    }
+
+    #[test]
+    fn split_at_prefix_graft() {
+        let mut mpt: MerkleTree<u32, u32> = MerkleTree::new();
+        for i in 0..500u32 {
+            mpt.insert(i, i);
+        }
+        let root_hash = mpt.compute_hashes();
+
+        //every shard, given by the first two bits of the key hash, is built by a different
+        //machine and then grafted back
+        let mut grafted: MerkleTree<u32, u32> = MerkleTree::new();
+        for shard in [[false, false], [false, true], [true, false], [true, true]].iter() {
+            let mut machine: MerkleTree<u32, u32> = MerkleTree::new();
+            for i in 0..500u32 {
+                let key_hash = hash(&i).unwrap().to_bytes();
+                if get_bit_direction(&key_hash, 0) == shard[0]
+                    && get_bit_direction(&key_hash, 1) == shard[1]
+                {
+                    machine.insert(i, i);
+                }
+            }
+            for (prefix, subtree) in machine.split_at_prefix(2) {
+                assert_eq!(&prefix[..], &shard[..]);
+                grafted.graft(&prefix, subtree).unwrap();
+            }
+        }
+        assert!(grafted.is_canonical());
+        assert_eq!(grafted.compute_hashes(), root_hash);
+
+        let mut rebuilt: MerkleTree<u32, u32> = MerkleTree::new();
+        for (prefix, subtree) in mpt.clone().split_at_prefix(3) {
+            rebuilt.graft(&prefix, subtree).unwrap();
+        }
+        assert!(rebuilt.is_canonical());
+        assert_eq!(rebuilt.compute_hashes(), root_hash);
+
+        //grafting an Empty subtree removes the whole range
+        rebuilt.graft(&[true], Empty::new().into()).unwrap();
+        assert!(rebuilt.is_canonical());
+        assert!(rebuilt.prove_range(&[true]).unwrap().get_leaves_in_range().is_empty());
+
+        //a Leaf outside of the prefix is refused
+        let outsider = mpt.get_node(&0).unwrap().clone();
+        let bit = get_bit_direction(&hash(&0u32).unwrap().to_bytes(), 0);
+        assert_eq!(rebuilt.graft(&[!bit], outsider), Err(()));
+    }
+
+    #[test]
+    fn merkle_tree_diff() {
+        let mut mpt1: MerkleTree<u32, u32> = MerkleTree::new();
+        let mut mpt2: MerkleTree<u32, u32> = MerkleTree::new();
+        for i in 0..300u32 {
+            mpt1.insert(i, i);
+        }
+        for i in 50..350u32 {
+            mpt2.insert(i, if i == 100 || i == 200 { i + 1 } else { i });
+        }
+        mpt1.compute_hashes();
+        mpt2.compute_hashes();
+        assert!(mpt1.diff(&mpt1.clone()).is_empty());
+
+        let changes = mpt1.diff(&mpt2);
+        assert_eq!(changes.len(), 50 + 50 + 2);
+        for change in changes {
+            match change {
+                Change::Added { key, value } => assert!(key >= 300 && value == key),
+                Change::Removed { key, value } => assert!(key < 50 && value == key),
+                Change::Changed { key, old, new } => {
+                    assert!(key == 100 || key == 200);
+                    assert_eq!((old, new), (key, key + 1));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn anti_entropy_converges() {
+        let mut source: MerkleTree<u32, u32> = MerkleTree::new();
+        let mut replica: MerkleTree<u32, u32> = MerkleTree::new();
+        for i in 0..400u32 {
+            source.insert(i, i);
+        }
+        for i in 20..420u32 {
+            replica.insert(i, if i == 100 { 0 } else { i });
+        }
+        let source_hash = source.compute_hashes();
+
+        //in-process transport: every message goes through serialization
+        let mut session = SyncSession::new();
+        let mut exchanged = 0;
+        while !session.is_done() {
+            while let Some(request) = session.next_request() {
+                let request: SyncRequest =
+                    serde_json::from_str(&serde_json::to_string(&request).unwrap()).unwrap();
+                let response = respond(&source, &request).unwrap();
+                let response: SyncResponse<u32, u32> =
+                    serde_json::from_str(&serde_json::to_string(&response).unwrap()).unwrap();
+                session.handle_response(&mut replica, response).unwrap();
+                exchanged += 1;
+            }
+        }
+
+        assert_eq!(session.get_target_hash(), Some(source_hash));
+        assert_eq!(replica.compute_hashes(), source_hash);
+        assert!(replica.is_canonical());
+        assert!(replica.diff(&source).is_empty());
+        assert!(exchanged < 400);
+    }
+
+    #[test]
+    fn anti_entropy_rejects_forged_responses() {
+        let mut source: MerkleTree<u32, u32> = MerkleTree::new();
+        let mut replica: MerkleTree<u32, u32> = MerkleTree::new();
+        for i in 0..50u32 {
+            source.insert(i, i);
+            replica.insert(i, if i == 7 { 0 } else { i });
+        }
+        let request = SyncRequest::new(vec![]);
+        assert_eq!(respond(&source, &request), Err(StaleHashes));
+        let source_hash = source.compute_hashes();
+        let replica_hash = replica.compute_hashes();
+
+        //a response for a prefix which was never asked for is rejected
+        let mut session = SyncSession::new();
+        let unrequested = SyncResponse::Leaves {
+            prefix: vec![true],
+            leaves: vec![Leaf::new(1000, 1000)],
+        };
+        assert_eq!(session.handle_response(&mut replica, unrequested), Err(()));
+
+        //a Leaf which does not match the Hash announced by the source is rejected
+        let mut forged = false;
+        while !session.is_done() {
+            while let Some(request) = session.next_request() {
+                let mut response = respond(&source, &request).unwrap();
+                if let SyncResponse::Leaves { leaves, .. } = &mut response {
+                    if !forged && leaves.len() == 1 && *leaves[0].get_key() == 7 {
+                        //the prefix is asked for again, and answered honestly the second time
+                        forged = true;
+                        leaves[0] = Leaf::new(7, 1000);
+                        assert_eq!(session.handle_response(&mut replica, response), Err(()));
+                        assert_eq!(replica.compute_hashes(), replica_hash);
+                        continue;
+                    }
+                }
+                session.handle_response(&mut replica, response).unwrap();
+            }
+        }
+        assert!(forged);
+        assert_eq!(replica.compute_hashes(), source_hash);
+    }
+
+    #[test]
+    fn entry_api_refreshes_hashes() {
+        let mut mpt: MerkleTree<&str, Vec<u32>> = MerkleTree::new();
+        assert_eq!(mpt.insert("AAAAA", vec![1]), None);
+        assert_eq!(mpt.insert("BBBBB", vec![2]), None);
+        assert_eq!(mpt.insert("AAAAA", vec![1, 2]), Some(vec![1]));
+        mpt.compute_hashes();
+
+        //appending transactions to the lists of the clients, after the hashes were computed
+        mpt.entry("AAAAA").or_insert_with(Vec::new).push(3);
+        mpt.entry("CCCCC").or_insert(vec![]).push(4);
+        mpt.entry("BBBBB").and_modify(|txs| txs.push(5)).or_insert(vec![]);
+        mpt.entry("DDDDD").and_modify(|txs| txs.push(6));
+        if let Some(mut txs) = mpt.get_mut("CCCCC") {
+            txs.push(7);
+        }
+        assert!(mpt.get_mut("EEEEE").is_none());
+        match mpt.entry("AAAAA") {
+            Entry::Occupied(mut e) => assert_eq!(e.insert(vec![8]), vec![1, 2, 3]),
+            Entry::Vacant(_) => assert!(false),
+        }
+
+        let mut expected: MerkleTree<&str, Vec<u32>> = MerkleTree::new();
+        expected.insert("AAAAA", vec![8]);
+        expected.insert("BBBBB", vec![2, 5]);
+        expected.insert("CCCCC", vec![4, 7]);
+        let root_hash = expected.compute_hashes();
+
+        assert!(mpt.is_canonical());
+        assert_eq!(mpt.compute_hashes(), root_hash);
+        let proof = mpt.prove("CCCCC");
+        assert_eq!(get_root_hash(proof, vec![4u32, 7], Id::new("CCCCC")), Ok(root_hash));
+    }
+
+    #[test]
+    fn entry_api_keeps_hashes_when_reading() {
+        let mut mpt: MerkleTree<&str, u32> = MerkleTree::new();
+        mpt.insert("AAAAA", 1);
+        mpt.insert("BBBBB", 2);
+        mpt.compute_hashes();
+
+        //looking up through the entry API, without writing anything
+        assert!(mpt.get_mut("CCCCC").is_none());
+        assert_eq!(*mpt.get_mut("AAAAA").unwrap(), 1);
+        match mpt.entry("BBBBB") {
+            Entry::Occupied(e) => assert_eq!(*e.get(), 2),
+            Entry::Vacant(_) => assert!(false),
+        }
+        mpt.entry("CCCCC").and_modify(|v| *v += 1);
+        assert!(!mpt.has_stale_hashes());
+        assert!(mpt.try_prove("AAAAA").is_ok());
+
+        *mpt.get_mut("AAAAA").unwrap() += 10;
+        assert!(mpt.has_stale_hashes());
+        mpt.entry("CCCCC").or_insert(3);
+        assert!(mpt.has_stale_hashes());
+        mpt.remove("CCCCC");
+
+        let mut arena: ArenaMerkleTree<&str, u32> = ArenaMerkleTree::new();
+        arena.insert("AAAAA", 1);
+        arena.insert("BBBBB", 2);
+        let root_hash = arena.compute_hashes();
+        assert_eq!(*arena.get_mut("AAAAA").unwrap(), 1);
+        assert_eq!(arena.compute_hashes(), root_hash);
+        *arena.get_mut("AAAAA").unwrap() += 10;
+        assert_eq!(arena.compute_hashes(), mpt.compute_hashes());
+    }
+
+    #[test]
+    fn len_is_not_serialized() {
+        let mut mpt: MerkleTree<u32, u32> = MerkleTree::new();
+        for i in 0..30u32 {
+            mpt.insert(i, i);
+        }
+        let root_hash = mpt.compute_hashes();
+
+        let json = serde_json::to_string(&mpt).unwrap();
+        assert!(!json.contains("\"len\""));
+        let mut decoded: MerkleTree<u32, u32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.len(), 30);
+        assert!(!decoded.has_stale_hashes());
+        assert_eq!(decoded.prove(7), mpt.prove(7));
+        assert_eq!(decoded, mpt);
+
+        //a Leaf added by hand is not counted until canonicalize, but takes part in equality
+        let mut by_hand = mpt.clone();
+        by_hand.get_mut_root().insert(30, 30, 0);
+        mpt.insert(30, 30);
+        assert_ne!(by_hand.len(), mpt.len());
+        assert_eq!(by_hand, mpt);
+        assert_ne!(mpt.compute_hashes(), root_hash);
+    }
+
+    #[test]
+    fn lookups_len_and_remove() {
+        let mut mpt: MerkleTree<String, u32> = MerkleTree::new();
+        assert!(mpt.is_empty());
+        for i in 0..100u32 {
+            mpt.insert(i.to_string(), i);
+        }
+        mpt.insert("42".to_string(), 4242);
+        mpt.entry("100".to_string()).or_insert(100);
+        assert_eq!(mpt.len(), 101);
+
+        //lookups of String keys through &str, without cloning
+        assert_eq!(mpt.get("42"), Some(&4242));
+        assert_eq!(mpt.get("101"), None);
+        assert!(mpt.contains_key("7"));
+        assert!(!mpt.contains_key("hello"));
+
+        for i in 50..101u32 {
+            assert!(mpt.remove(i.to_string().as_str()).is_some());
+        }
+        assert_eq!(mpt.remove("50"), None);
+        assert_eq!(mpt.len(), 50);
+        assert!(!mpt.contains_key("60"));
+        assert!(mpt.is_canonical());
+
+        let mut expected: MerkleTree<String, u32> = MerkleTree::new();
+        for i in 0..50u32 {
+            expected.insert(i.to_string(), if i == 42 { 4242 } else { i });
+        }
+        assert_eq!(mpt.compute_hashes(), expected.compute_hashes());
+
+        for i in 0..50u32 {
+            mpt.remove(i.to_string().as_str());
+        }
+        assert!(mpt.is_empty());
+        assert!(mpt.is_canonical());
+        assert_eq!(mpt.compute_hashes(), MerkleTree::<String, u32>::new().compute_hashes());
+    }
+
+    #[test]
+    fn try_insert_collision() {
+        //two different keys serialized, hence hashed, the same way
+        #[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+        struct Account {
+            name: String,
+            #[serde(skip)]
+            branch: u8,
+        }
+        impl KeyPath for Account {}
+        let first = Account { name: "AAAAA".to_string(), branch: 0 };
+        let second = Account { name: "AAAAA".to_string(), branch: 1 };
+
+        let mut mpt: MerkleTree<Account, u32> = MerkleTree::new();
+        for i in 0..20u32 {
+            mpt.insert(Account { name: i.to_string(), branch: 0 }, i);
+        }
+        assert_eq!(mpt.try_insert(first.clone(), 1), Ok(None));
+        let root_hash = mpt.compute_hashes();
+
+        assert_eq!(mpt.try_insert(second.clone(), 2), Err(Collision));
+        assert_eq!(mpt.len(), 21);
+        assert!(!mpt.contains_key(&second));
+        assert!(mpt.is_canonical());
+        assert_eq!(mpt.compute_hashes(), root_hash);
+        assert_eq!(mpt.try_insert(first.clone(), 3), Ok(Some(1)));
+
+        let leaves = vec![Leaf::new(first, 1), Leaf::new(second, 2)];
+        assert_eq!(mpt.replace_range(&[], leaves), Err(()));
+        assert_eq!(mpt.len(), 21);
+    }
+
+    #[test]
+    fn proofs_follow_the_path_of_the_key() {
+        //a Leaf placed on the Left while the path of its key goes Right
+        let key = (0..100u32).find(|i| get_bit_direction(&i.key_path(), 0)).unwrap();
+        let leaf = Leaf::new(key, 5u32);
+        let mut root: NodeGeneric<u32, u32> = Internal::new(leaf.into(), Empty::new().into(), None).into();
+        let root_hash = root.compute_hashes();
+        let proof = Proof::new(vec![Sibling::new(Empty::get_hash(), Right {}.into())]);
+        assert_eq!(get_root_hash_from_leaf_hash(proof.clone(), leaf.get_hash()), root_hash);
+
+        assert_eq!(get_root_hash(proof.clone(), 5u32, Id::new(key)), Err(()));
+        assert_eq!(get_root_hash_from_value_hash(proof.clone(), Id::new(key), hash(&5u32).unwrap()), Err(()));
+        assert_eq!(get_proof_root_hash(&proof, 5u32, Id::new(key)), Err(()));
+        assert!(!BatchVerifier::new(root_hash).verify(&proof, 5u32, Id::new(key)));
+        let opened = OpenedProof::new(key, 5u32, None, proof.get_siblings().clone(), None);
+        assert_eq!(opened.verify(), Err(()));
+
+        //a hexary or sparse proof is bound to the path of the key it was issued for
+        let mut hexary: HexaryMerkleTree<u32, u32> = HexaryMerkleTree::new();
+        let mut smt: SparseMerkleTree<u32, u32> = SparseMerkleTree::new();
+        for i in 0..50u32 {
+            hexary.insert(i, i);
+            smt.insert(i, i);
+        }
+        hexary.compute_hashes();
+        smt.compute_hashes();
+        let other = (1..50u32).find(|i| get_digit(&i.key_path(), 0, 4) != get_digit(&0u32.key_path(), 0, 4)).unwrap();
+        assert!(get_proof_root_hash(&hexary.prove(&0), 0u32, Id::new(0u32)).is_ok());
+        assert_eq!(get_proof_root_hash(&hexary.prove(&0), 0u32, Id::new(other)), Err(()));
+        assert!(get_proof_root_hash(&smt.prove(&0), 0u32, Id::new(0u32)).is_ok());
+        assert_eq!(get_proof_root_hash(&smt.prove(&0), 0u32, Id::new(other)), Err(()));
+    }
+
+    #[test]
+    fn composite_and_hashed_keys() {
+        #[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+        struct Account {
+            name: String,
+            branch: u8,
+        }
+
+        let mut pairs: MerkleTree<(u32, String), u32> = MerkleTree::new();
+        let mut arrays: MerkleTree<[u8; 4], u32> = MerkleTree::new();
+        let mut accounts: MerkleTree<HashedKey<Account>, u32> = MerkleTree::new();
+        for i in 0..50u32 {
+            pairs.insert((i, i.to_string()), i);
+            arrays.insert(i.to_le_bytes(), i);
+            accounts.insert(HashedKey(Account { name: i.to_string(), branch: 0 }), i);
+        }
+        assert_eq!(pairs.get(&(7, "7".to_string())), Some(&7));
+        assert_eq!(arrays.get(&7u32.to_le_bytes()), Some(&7));
+        let account = HashedKey(Account { name: "7".to_string(), branch: 0 });
+        assert_eq!(accounts.get(&account), Some(&7));
+
+        //the wrapper is transparent: the path of the key is the Hash of the wrapped value
+        assert_eq!(account.key_path(), hash(&account.0).unwrap().to_bytes());
+        let root_hash = accounts.compute_hashes();
+        let proof = accounts.prove(account.clone());
+        assert_eq!(get_root_hash(proof, 7u32, Id::new(account)), Ok(root_hash));
+
+        //a String key is found through its borrowed form, whose path is the same
+        let mut names: MerkleTree<String, u32> = MerkleTree::new();
+        names.insert("AAAAA".to_string(), 1);
+        assert_eq!("AAAAA".key_path(), "AAAAA".to_string().key_path());
+        assert_eq!(names.get("AAAAA"), Some(&1));
+    }
+
+    #[test]
+    fn insert_keys_sharing_254_bits() {
+        #[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+        struct RawPath([u8; 32]);
+        impl KeyPath for RawPath {
+            fn key_path(&self) -> [u8; 32] {
+                self.0
+            }
+        }
+        //the paths differ at bit 254 only, so that both Leaves sit at depth 255
+        let first = RawPath([0u8; 32]);
+        let mut path = [0u8; 32];
+        path[31] = 0b10;
+        let second = RawPath(path);
+        path[31] = 0b01;
+        let third = RawPath(path);
+
+        let mut mpt: MerkleTree<RawPath, u32> = MerkleTree::new();
+        mpt.insert(RawPath([0xff; 32]), 0);
+        assert_eq!(mpt.try_insert(first.clone(), 1), Ok(None));
+        assert_eq!(mpt.try_insert(second.clone(), 2), Ok(None));
+        assert_eq!(mpt.try_insert(third, 3), Err(Collision));
+        assert_eq!(mpt.len(), 3);
+        assert!(mpt.is_canonical());
+
+        let root_hash = mpt.compute_hashes();
+        let proof = mpt.prove(second.clone());
+        assert_eq!(proof.get_siblings().len(), 255);
+        assert_eq!(get_root_hash(proof, 2u32, Id::new(second)), Ok(root_hash));
+        assert_eq!(mpt.get(&first), Some(&1));
+    }
+
+    #[test]
+    fn key_path_ordered_keys() {
+        //heights placed in order, from the leftmost Leaf to the rightmost one
+        #[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+        struct Height(u64);
+        impl KeyPath for Height {
+            fn key_path(&self) -> [u8; 32] {
+                let mut path = [0u8; 32];
+                path[..8].copy_from_slice(&self.0.to_be_bytes());
+                path
+            }
+        }
+
+        let mut mpt: MerkleTree<Height, u64> = MerkleTree::new();
+        for i in 0..64u64 {
+            let height = (i * 37) % 64;
+            mpt.insert(Height(height), height * 10);
+        }
+        assert!(mpt.is_canonical());
+        let mut leaves = Vec::new();
+        mpt.get_root().get_leaves(&mut leaves);
+        let heights: Vec<u64> = leaves.iter().map(|leaf| leaf.get_key().0).collect();
+        assert_eq!(heights, (0..64).collect::<Vec<u64>>());
+
+        let root_hash = mpt.compute_hashes();
+        let proof = mpt.prove(Height(42));
+        assert_eq!(get_root_hash(proof, 420u64, Id::new(Height(42))), Ok(root_hash));
+
+        //Hashes are used as their own path
+        let mut hashed: MerkleTree<Hash, u32> = MerkleTree::new();
+        for i in 0..50u32 {
+            hashed.insert(hash(&i).unwrap(), i);
+        }
+        assert_eq!(hash(&7u32).unwrap().key_path(), hash(&7u32).unwrap().to_bytes());
+        assert_eq!(hashed.get(&hash(&7u32).unwrap()), Some(&7));
+        assert!(hashed.is_canonical());
+        let root_hash = hashed.compute_hashes();
+        let proof = hashed.prove(hash(&7u32).unwrap());
+        assert_eq!(get_root_hash(proof, 7u32, Id::new(hash(&7u32).unwrap())), Ok(root_hash));
+    }
+
+    #[test]
+    fn hexary_proofs_against_binary() {
+        let mut mpt: MerkleTree<u32, u32> = MerkleTree::new();
+        let mut hexary: HexaryMerkleTree<u32, u32> = HexaryMerkleTree::new();
+        for i in 0..300u32 {
+            mpt.insert(i, i * 2);
+            hexary.insert(300 - i, (300 - i) * 2);
+        }
+        assert_eq!(hexary.insert(0, 0), None);
+        assert_eq!(hexary.insert(7, 0), Some(14));
+        hexary.insert(7, 14);
+        assert_eq!(hexary.len(), 301);
+        assert_eq!(hexary.get(&42), Some(&84));
+        assert_eq!(hexary.get(&301), None);
+
+        let root_hash = mpt.compute_hashes();
+        let hexary_root_hash = hexary.compute_hashes();
+        for i in [1u32, 42, 299].iter() {
+            let binary_proof = mpt.prove(*i);
+            let hexary_proof = hexary.prove(i);
+            assert!(hexary_proof.get_hash_count() > binary_proof.get_hash_count());
+            assert!(hexary_proof.get_levels().len() < binary_proof.get_siblings().len());
+            assert_eq!(get_proof_root_hash(&binary_proof, i * 2, Id::new(*i)), Ok(root_hash));
+            assert_eq!(get_proof_root_hash(&hexary_proof, i * 2, Id::new(*i)), Ok(hexary_root_hash));
+            assert_ne!(get_proof_root_hash(&hexary_proof, i * 3, Id::new(*i)), Ok(hexary_root_hash));
+        }
+        assert!(hexary.prove(&301).get_levels().is_empty());
+
+        //the Hash of the root does not depend on the order of insertion
+        let mut reversed: HexaryMerkleTree<u32, u32> = HexaryMerkleTree::new();
+        for i in (0..301u32).rev() {
+            reversed.insert(i, i * 2);
+        }
+        assert_eq!(reversed.compute_hashes(), hexary_root_hash);
+    }
+
+    #[test]
+    fn bench_small_batch() {
+        let results = run_batch(1_000);
+        let operations: Vec<&str> = results.iter().map(|result| result.get_operation()).collect();
+        assert_eq!(operations, vec!["insert", "compute_hashes", "prove", "verify", "prove_hexary"]);
+
+        let binary_bytes = results[2].get_avg_proof_bytes().unwrap();
+        let hexary_bytes = results[4].get_avg_proof_bytes().unwrap();
+        assert!(binary_bytes > 0.0);
+        assert!(hexary_bytes > binary_bytes);
+        assert!(serde_json::to_string(&results[0]).unwrap().starts_with("{\"operation\":\"insert\""));
+    }
+
+    #[test]
+    fn arena_matches_merkle_tree() {
+        let mut mpt: MerkleTree<u32, u32> = MerkleTree::new();
+        let mut arena: ArenaMerkleTree<u32, u32> = ArenaMerkleTree::new();
+        for i in 0..500u32 {
+            assert_eq!(arena.insert(i, i), mpt.insert(i, i));
+        }
+        assert_eq!(arena.insert(7, 70), Some(7));
+        mpt.insert(7, 70);
+        *arena.get_mut(&8).unwrap() += 1;
+        *mpt.get_mut(&8).unwrap() += 1;
+        for i in (0..500u32).filter(|i| i % 3 == 0) {
+            assert_eq!(arena.remove(&i), mpt.remove(&i));
+        }
+        assert_eq!(arena.remove(&3), None);
+        assert_eq!(arena.len(), mpt.len());
+        assert_eq!(arena.get(&8), Some(&9));
+        assert!(!arena.contains_key(&9));
+
+        let root_hash = mpt.compute_hashes();
+        assert_eq!(arena.compute_hashes(), root_hash);
+        for i in [1u32, 7, 8, 9, 499].iter() {
+            assert_eq!(arena.prove(*i), mpt.prove(*i));
+        }
+        assert_eq!(arena.to_merkle_tree().compute_hashes(), root_hash);
+        assert_eq!(ArenaMerkleTree::from_merkle_tree(&mpt).compute_hashes(), root_hash);
+
+        //the slots of removed nodes are reused, and Empty nodes are never allocated
+        let node_count = arena.get_node_count();
+        for i in (0..500u32).filter(|i| i % 3 == 0) {
+            arena.insert(i, i);
+        }
+        for i in (0..500u32).filter(|i| i % 3 == 0) {
+            arena.remove(&i);
+        }
+        assert_eq!(arena.get_node_count(), node_count);
+        for i in 0..500u32 {
+            arena.remove(&i);
+        }
+        assert_eq!(arena.get_node_count(), 1);
+        assert_eq!(arena.compute_hashes(), MerkleTree::<u32, u32>::new().compute_hashes());
+    }
+
+    #[test]
+    fn arena_matches_merkle_tree_api() {
+        let mut mpt: MerkleTree<u32, u32> = MerkleTree::new();
+        let mut arena: ArenaMerkleTree<u32, u32> = ArenaMerkleTree::new();
+        for i in 0..300u32 {
+            *mpt.entry(i % 200).or_insert(0) += i;
+            *arena.entry(i % 200).or_insert(0) += i;
+        }
+        arena.entry(500).and_modify(|v| *v += 1);
+        assert_eq!(arena.get_salt(&500), None);
+        assert!(arena.is_canonical());
+        assert!(arena.has_stale_hashes());
+        assert_eq!(arena.compute_hashes_parallel(3), mpt.compute_hashes());
+        assert!(!arena.has_stale_hashes());
+        assert!(arena.is_canonical());
+
+        //reading through an occupied Entry keeps the cached Hashes
+        assert_eq!(*arena.entry(7).or_insert(0), *mpt.get_value(&7));
+        assert!(!arena.has_stale_hashes());
+
+        let opened = arena.prove_opened(&7).unwrap();
+        assert_eq!(opened.verify(), Ok(mpt.compute_hashes()));
+        assert_eq!(opened.get_siblings(), mpt.prove_opened(&7).unwrap().get_siblings());
+        assert!(arena.prove_opened(&500).is_none());
+
+        for prefix in [vec![], vec![true], vec![false, true, true], vec![true; 12]].iter() {
+            let range_proof = arena.prove_range(prefix).unwrap();
+            assert_eq!(range_proof.get_leaves(), mpt.prove_range(prefix).unwrap().get_leaves());
+            assert_eq!(range_proof.get_siblings(), mpt.prove_range(prefix).unwrap().get_siblings());
+        }
+
+        let old = arena.clone();
+        arena.insert(7, 0);
+        arena.remove(&8);
+        arena.insert(1000, 1000);
+        assert!(arena.try_prove_range(&[false]).is_err() || arena.try_prove_range(&[true]).is_err());
+        arena.compute_hashes();
+        let mut changes = old.diff(&arena);
+        let mut expected = mpt.diff(&arena.to_merkle_tree());
+        changes.sort_by_key(|change| format!("{:?}", change));
+        expected.sort_by_key(|change| format!("{:?}", change));
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes, expected);
+
+        //splitting the arena and grafting every subtree back onto the old one
+        let root_hash = arena.compute_hashes();
+        let mut rebuilt = old.clone();
+        for (prefix, subtree) in arena.clone().split_at_prefix(4) {
+            assert!(rebuilt.graft(&prefix, subtree).is_ok());
+        }
+        assert_eq!(rebuilt.len(), arena.len());
+        assert!(rebuilt.is_canonical());
+        assert_eq!(rebuilt.compute_hashes(), root_hash);
+        let outside = (0..100u32).find(|i| !get_bit_direction(&i.key_path(), 0)).unwrap();
+        assert_eq!(rebuilt.graft(&[true], Leaf::new(outside, 0).into()), Err(()));
+        assert_eq!(rebuilt.compute_hashes(), root_hash);
+    }
+
+    #[test]
+    fn sparse_merkle_tree_fixed_depth() {
+        //reference Hash of the full tree of depth 256 holding the given Leaves
+        fn full_tree_hash(leaves: Vec<([u8; 32], Hash)>, depth: usize) -> Hash {
+            if leaves.is_empty() {
+                return get_default_hashes()[256 - depth];
+            } else if depth == 256 {
+                return leaves[0].1;
+            }
+            let (right, left): (Vec<_>, Vec<_>) = leaves
+                .into_iter()
+                .partition(|(path, _)| get_bit_direction(path, depth as u8));
+            Internal::<u32, u32>::create_hash(full_tree_hash(left, depth + 1), full_tree_hash(right, depth + 1))
+        }
+
+        let mut smt: SparseMerkleTree<u32, u32> = SparseMerkleTree::new();
+        assert_eq!(smt.compute_hashes(), get_default_hashes()[256]);
+        for i in 0..20u32 {
+            smt.insert(i, i * 2);
+        }
+        let leaves: Vec<([u8; 32], Hash)> = (0..20u32)
+            .map(|i| (i.key_path(), Leaf::new(i, i * 2).get_hash()))
+            .collect();
+        let root_hash = smt.compute_hashes();
+        assert_eq!(root_hash, full_tree_hash(leaves, 0));
+
+        //proofs of membership, compressed against the default Hashes
+        for i in [0u32, 7, 19].iter() {
+            let proof = smt.prove(i);
+            assert!(proof.get_hash_count() < 16);
+            assert_eq!(get_proof_root_hash(&proof, i * 2, Id::new(*i)), Ok(root_hash));
+            assert_ne!(get_proof_root_hash(&proof, i * 2 + 1, Id::new(*i)), Ok(root_hash));
+        }
+        //proofs of absence, whether the path ends on an Empty node or on the Leaf of another key
+        for i in 20..40u32 {
+            let proof = smt.prove(&i);
+            assert_eq!(proof.get_key_path(), &i.key_path());
+            assert_eq!(proof.get_root_hash_from(Empty::get_hash()), Ok(root_hash));
+        }
+
+        //the cached Hashes along the paths of inserted and removed keys are discarded
+        smt.insert(100, 0);
+        assert_ne!(smt.compute_hashes(), root_hash);
+        smt.insert(5, 0);
+        smt.insert(5, 10);
+        assert_eq!(smt.remove(&100), Some(0));
+        assert_eq!(smt.compute_hashes(), root_hash);
+        assert_eq!(smt.len(), 20);
+
+        //equality ignores the cache of the Hashes, which stale proofs are told about
+        let mut copy = smt.clone();
+        copy.insert(100, 0);
+        copy.remove(&100);
+        assert_eq!(copy, smt);
+        assert_eq!(copy.try_prove(&5), Err(StaleHashes));
+        copy.compute_hashes();
+        assert_eq!(copy.try_prove(&5), Ok(smt.prove(&5)));
+    }
+
+    #[test]
+    fn prove_with_stale_hashes() {
+        let mut mpt: MerkleTree<u32, u32> = MerkleTree::new();
+        for i in 0..64u32 {
+            mpt.insert(i, i);
+        }
+        assert!(mpt.has_stale_hashes());
+        assert_eq!(mpt.try_prove(3), Err(StaleHashes));
+
+        let root_hash = mpt.compute_hashes();
+        assert!(!mpt.has_stale_hashes());
+        assert!(mpt.try_prove(3).is_ok());
+
+        //only the Hashes along the path of the modified key are computed again
+        mpt.insert(3, 0);
+        assert!(mpt.has_stale_hashes());
+        let other = (0..64u32)
+            .find(|i| get_bit_direction(&i.key_path(), 0) != get_bit_direction(&3u32.key_path(), 0))
+            .unwrap();
+        assert_eq!(mpt.try_prove(other), Err(StaleHashes));
+        let proof = mpt.compute_hashes_prove(3);
+        let mut full = mpt.clone();
+        assert_eq!(full.get_mut_root().compute_hashes(), mpt.compute_hashes());
+        assert_eq!(get_root_hash(proof, 0, Id::new(3)), Ok(mpt.compute_hashes()));
+
+        mpt.insert(3, 3);
+        assert_eq!(mpt.compute_hashes(), root_hash);
+        assert_eq!(mpt.remove(&64), None);
+        assert!(!mpt.has_stale_hashes());
+
+        //proving straight after a modification computes the stale Hashes first
+        mpt.insert(other, 0);
+        assert_eq!(get_root_hash(mpt.prove(other), 0, Id::new(other)), Ok(mpt.compute_hashes()));
+        mpt.insert(other, other);
+        let range_proof = mpt.prove_range(&[true]).unwrap();
+        assert_eq!(get_range_root_hash(&range_proof), Ok(root_hash));
+        mpt.insert(3, 0);
+        assert_eq!(mpt.prove_opened(&3).unwrap().verify(), Ok(mpt.compute_hashes()));
+
+        let mut arena = ArenaMerkleTree::from_merkle_tree(&mpt);
+        arena.insert(3, 3);
+        assert!(arena.has_stale_hashes());
+        assert_eq!(get_root_hash(arena.prove(3), 3, Id::new(3)), Ok(root_hash));
+        arena.insert(3, 0);
+        assert_eq!(arena.prove_opened(&3).unwrap().verify(), Ok(mpt.compute_hashes()));
+        arena.insert(3, 3);
+        assert_eq!(get_range_root_hash(&arena.prove_range(&[false]).unwrap()), Ok(root_hash));
+
+        let mut smt: SparseMerkleTree<u32, u32> = SparseMerkleTree::new();
+        let mut hexary: HexaryMerkleTree<u32, u32> = HexaryMerkleTree::new();
+        for i in 0..64u32 {
+            smt.insert(i, i);
+            hexary.insert(i, i);
+        }
+        assert!(smt.try_prove(&3).is_err());
+        assert!(hexary.has_stale_hashes());
+        assert_eq!(hexary.try_prove(&3), Err(StaleHashes));
+        let proof = smt.prove(&3);
+        assert_eq!(get_proof_root_hash(&proof, 3u32, Id::new(3u32)), Ok(smt.compute_hashes()));
+        let proof = hexary.prove(&3);
+        assert!(!hexary.has_stale_hashes());
+        let hexary_hash = hexary.compute_hashes();
+        assert_eq!(get_proof_root_hash(&proof, 3u32, Id::new(3u32)), Ok(hexary_hash));
+
+        //only the Branches along the path of the modified key are computed again
+        hexary.insert(3, 0);
+        assert_eq!(hexary.try_prove(&other), Err(StaleHashes));
+        let proof = hexary.prove(&3);
+        let mut full = hexary.clone();
+        full.insert(other, other);
+        assert_eq!(full.compute_hashes(), hexary.compute_hashes());
+        assert_eq!(get_proof_root_hash(&proof, 0u32, Id::new(3u32)), Ok(hexary.compute_hashes()));
+        hexary.insert(3, 3);
+        assert_eq!(hexary.compute_hashes(), hexary_hash);
+    }
+
+    #[test]
+    fn opened_proof_verify() {
+        let mut mpt: MerkleTree<String, u32> = MerkleTree::new();
+        for i in 0..16u32 {
+            mpt.insert(format!("key{}", i), i);
+        }
+        mpt.insert_salted("salted".to_string(), 100, hash(&"salt").unwrap());
+        let root_hash = mpt.compute_hashes();
+        assert_eq!(mpt.prove_opened("missing"), None);
+
+        for key in ["key3", "salted"].iter() {
+            let opened = mpt.prove_opened(*key).unwrap();
+            assert_eq!(opened.get_claimed_root(), Some(&root_hash));
+            //the evidence survives a round trip through serde on its own
+            let json = serde_json::to_string(&opened).unwrap();
+            let stored: OpenedProof<String, u32> = serde_json::from_str(&json).unwrap();
+            assert_eq!(stored.verify(), Ok(root_hash));
+            assert!(stored.verify_against(root_hash));
+        }
+
+        let opened = mpt.prove_opened("key3").unwrap();
+        let forged = OpenedProof::new(
+            "key3".to_string(),
+            4,
+            None,
+            opened.get_siblings().clone(),
+            Some(root_hash),
+        );
+        assert_eq!(forged.verify(), Err(()));
+        //without a claimed root, a forged opening is only caught against the trusted root
+        let unrooted = OpenedProof::new("key3".to_string(), 4, None, opened.get_siblings().clone(), None);
+        assert!(unrooted.verify().is_ok());
+        assert!(!unrooted.verify_against(root_hash));
+    }
+
+    #[test]
+    fn verify_from_precomputed_hashes() {
+        let mut mpt: MerkleTree<&str, Vec<u8>> = MerkleTree::new();
+        let salt = hash(&7u8).unwrap();
+        mpt.insert("AAAAA", vec![1, 2, 3]);
+        mpt.insert("BBBBB", vec![4, 5]);
+        mpt.insert_salted("CCCCC", vec![6], salt);
+        let root_hash = mpt.compute_hashes();
+
+        let value_hash = hash(&vec![1u8, 2, 3]).unwrap();
+        let leaf_hash = Leaf::create_leaf_hash("AAAAA", vec![1u8, 2, 3]);
+        assert_eq!(get_root_hash_from_leaf_hash(mpt.prove("AAAAA"), leaf_hash), root_hash);
+        assert_eq!(get_root_hash_from_value_hash(mpt.prove("AAAAA"), Id::new("AAAAA"), value_hash), Ok(root_hash));
+        assert_ne!(get_root_hash_from_value_hash(mpt.prove("BBBBB"), Id::new("BBBBB"), value_hash), Ok(root_hash));
+
+        let value_hash = hash(&vec![6u8]).unwrap();
+        let proof = mpt.prove("CCCCC");
+        assert_eq!(get_root_hash_salted_from_value_hash(proof, Id::new("CCCCC"), value_hash, salt), Ok(root_hash));
+    }
+
+    #[test]
+    fn batch_verification() {
+        let mut mpt: MerkleTree<u64, u64> = MerkleTree::new();
+        for i in 0..256u64 {
+            mpt.insert(i, i * 10);
+        }
+        let root_hash = mpt.compute_hashes();
+
+        let mut items: Vec<(Proof, u64, Id<u64>)> = (0..32u64).map(|i| (mpt.prove(i), i * 10, Id::new(i))).collect();
+        //a wrong value and a proof of another key
+        items.push((mpt.prove(40), 0, Id::new(40)));
+        items.push((mpt.prove(41), 420, Id::new(42)));
+        let separate: usize = items.iter().map(|(proof, _, _)| proof.get_siblings().len()).sum();
+
+        let mut verifier = BatchVerifier::new(root_hash);
+        let results: Vec<bool> = items
+            .iter()
+            .map(|(proof, value, id)| verifier.verify(proof, *value, id.clone()))
+            .collect();
+        assert!(results[..32].iter().all(|passed| *passed));
+        assert_eq!(&results[32..], &[false, false]);
+        assert!(verifier.get_hash_count() < separate);
+
+        assert_eq!(verify_batch(root_hash, items), results);
+    }
+
+    #[test]
+    fn root_log_snapshots() {
+        let mut log: RootLog<u32, u32> = RootLog::new();
+        let mut mpt: MerkleTree<u32, u32> = MerkleTree::new();
+        let mut roots = Vec::<Hash>::new();
+        for batch in 0..4u32 {
+            for i in 0..10u32 {
+                mpt.insert(batch * 10 + i, batch);
+            }
+            let record = if batch % 2 == 0 {
+                log.seal_snapshot(mpt.clone())
+            } else {
+                log.seal(&mut mpt)
+            };
+            assert_eq!(record.get_sequence(), batch as u64);
+            assert_eq!(record.get_leaf_count(), (batch as usize + 1) * 10);
+            roots.push(*record.get_root_hash());
+        }
+        assert_eq!(log.len(), 4);
+
+        //the keys of batch 2 are proven against the root of batch 2 only
+        let proof = log.prove_at(2, &25).unwrap();
+        assert_eq!(get_root_hash(proof, 2u32, Id::new(25u32)), Ok(roots[2]));
+        assert_eq!(log.prove_at(0, &25).unwrap().get_siblings().len(), 0);
+        assert_eq!(log.prove_at(1, &5), None);
+
+        //every record is committed by the Hash of the log
+        for sequence in 0..4u64 {
+            let record_hash = log.get_record(sequence).unwrap().get_hash();
+            let proof = log.prove_record(sequence).unwrap();
+            assert_eq!(get_entry_root_hash(proof, sequence, 4, record_hash), Ok(log.get_log_hash()));
+        }
+        let forged = RootRecord::new(1, roots[0], 0, 20).get_hash();
+        let proof = log.prove_record(1).unwrap();
+        assert_ne!(get_entry_root_hash(proof.clone(), 1, 4, forged), Ok(log.get_log_hash()));
+        assert_eq!(get_entry_root_hash(proof, 2, 4, log.get_record(1).unwrap().get_hash()), Err(()));
+        assert_eq!(log.prove_record(4), None);
+
+        //the log only grows: every old Hash of the log is consistent with the current one
+        for old_len in 0..=4usize {
+            let consistency = log.prove_consistency(old_len);
+            assert!(consistency.verify(log.get_log_hash_at(old_len), log.get_log_hash()));
+        }
+
+        //modifying a MerkleTree records nothing until it is sealed
+        mpt.insert(100, 4);
+        mpt.compute_hashes();
+        assert_eq!(log.len(), 4);
+        log.seal(&mut mpt);
+        assert_eq!(log.len(), 5);
+
+        //the snapshots are looked up through any borrowed form of the key
+        let mut log: RootLog<String, u32> = RootLog::new();
+        let mut mpt: MerkleTree<String, u32> = MerkleTree::new();
+        mpt.insert("key".to_string(), 1);
+        mpt.insert("other".to_string(), 2);
+        let root_hash = *log.seal_snapshot(mpt).get_root_hash();
+        let proof = log.prove_at(0, "key").unwrap();
+        assert_eq!(get_root_hash(proof, 1u32, Id::new("key".to_string())), Ok(root_hash));
+        assert_eq!(log.prove_at(0, "missing").unwrap().get_siblings().len(), 0);
+    }
+
+    #[test]
+    fn accumulator_inclusion_and_consistency() {
+        let roots: Vec<Hash> = (0..20u32).map(|i| hash(&i).unwrap()).collect();
+        let mut accumulators = vec![MerkleAccumulator::new()];
+        for root in roots.iter() {
+            let mut acc = accumulators.last().unwrap().clone();
+            acc.append(*root);
+            accumulators.push(acc);
+        }
+        let acc = accumulators.last().unwrap();
+        assert_eq!(acc.get_hash_at(0), Empty::get_hash());
+
+        for size in 1..=20u64 {
+            let size_hash = acc.get_hash_at(size);
+            assert_eq!(accumulators[size as usize].get_hash(), size_hash);
+            for sequence in 0..size {
+                let proof = acc.prove_at(sequence, size).unwrap();
+                let root = roots[sequence as usize];
+                assert_eq!(get_entry_root_hash(proof.clone(), sequence, size, root), Ok(size_hash));
+                assert_ne!(get_entry_root_hash(proof, sequence, size, hash(&"forged").unwrap()), Ok(size_hash));
+            }
+            assert_eq!(acc.prove_at(size, size), None);
+        }
+
+        for new_size in 0..=20usize {
+            let new_acc = &accumulators[new_size];
+            for old_size in 0..=new_size {
+                let proof = new_acc.prove_consistency(old_size as u64);
+                let old_hash = accumulators[old_size].get_hash();
+                assert!(proof.verify(old_hash, new_acc.get_hash()));
+                if old_size > 0 && old_size < new_size {
+                    //a different history for the old entries is not consistent
+                    let mut forked = MerkleAccumulator::new();
+                    for root in roots.iter().take(old_size - 1) {
+                        forked.append(*root);
+                    }
+                    forked.append(hash(&"forked").unwrap());
+                    assert!(!proof.verify(forked.get_hash(), new_acc.get_hash()));
+                    assert!(!proof.verify(old_hash, accumulators[new_size - 1].get_hash()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn journal_recovery_after_truncation() {
+        let dir = std::env::temp_dir().join(format!("journal_recovery_{}", std::process::id()));
+        let crash_dir = dir.join("crash");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut journaled: JournaledMerkleTree<u32, u32> = JournaledMerkleTree::open(&dir).unwrap();
+        let mut expected: MerkleTree<u32, u32> = MerkleTree::new();
+        for i in 0..10u32 {
+            journaled.insert(i, i).unwrap();
+            expected.insert(i, i);
+        }
+        assert_eq!(journaled.snapshot().unwrap(), expected.compute_hashes());
+
+        //the size of the journal and the root after every mutation since the snapshot
+        let journal_path = dir.join(JOURNAL_FILE);
+        let mut checkpoints = vec![(0u64, expected.compute_hashes())];
+        for i in 5..15u32 {
+            if i % 3 == 0 {
+                journaled.remove(&i).unwrap();
+                expected.remove(&i);
+            } else {
+                journaled.insert(i, i * 2).unwrap();
+                expected.insert(i, i * 2);
+            }
+            checkpoints.push((std::fs::metadata(&journal_path).unwrap().len(), expected.compute_hashes()));
+        }
+        drop(journaled);
+        let bytes = std::fs::read(&journal_path).unwrap();
+        let snapshot = std::fs::read(dir.join(SNAPSHOT_FILE)).unwrap();
+
+        let crash = |journal: &[u8]| {
+            std::fs::create_dir_all(&crash_dir).unwrap();
+            std::fs::write(crash_dir.join(SNAPSHOT_FILE), &snapshot).unwrap();
+            std::fs::write(crash_dir.join(JOURNAL_FILE), journal).unwrap();
+            JournaledMerkleTree::<u32, u32>::open(&crash_dir).unwrap()
+        };
+        for offset in 0..=bytes.len() {
+            let mut recovered = crash(&bytes[..offset]);
+            let (len, root) = checkpoints.iter().rev().find(|(len, _)| *len as usize <= offset).unwrap();
+            assert_eq!(recovered.compute_hashes(), *root);
+            //the torn record is dropped, so that the next ones follow the last complete one
+            assert_eq!(std::fs::metadata(crash_dir.join(JOURNAL_FILE)).unwrap().len(), *len);
+        }
+
+        //a corrupted record stops the replay, and the journal goes on from there
+        let mut corrupted = bytes.clone();
+        corrupted[checkpoints[3].0 as usize + 40] ^= 1;
+        let mut recovered = crash(&corrupted);
+        assert_eq!(recovered.compute_hashes(), checkpoints[3].1);
+        recovered.insert(100, 100).unwrap();
+        let root = recovered.compute_hashes();
+        drop(recovered);
+        let mut reopened = JournaledMerkleTree::<u32, u32>::open(&crash_dir).unwrap();
+        assert_eq!(reopened.compute_hashes(), root);
+        assert_eq!(reopened.get_tree().get(&100), Some(&100));
+
+        //the bytes of a record torn by a failed write, while the journal is open, are
+        //dropped by the next append instead of hiding it from the replay
+        let mut torn = std::fs::OpenOptions::new().append(true).open(crash_dir.join(JOURNAL_FILE)).unwrap();
+        std::io::Write::write_all(&mut torn, &bytes[..checkpoints[1].0 as usize - 1]).unwrap();
+        drop(torn);
+        reopened.insert(200, 200).unwrap();
+        let root = reopened.compute_hashes();
+        drop(reopened);
+        let mut reopened = JournaledMerkleTree::<u32, u32>::open(&crash_dir).unwrap();
+        assert_eq!(reopened.compute_hashes(), root);
+        assert_eq!(reopened.get_tree().get(&200), Some(&200));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn root_log_prune() {
+        let mut log: RootLog<u32, u32> = RootLog::new();
+        let mut mpt: MerkleTree<u32, u32> = MerkleTree::new();
+        let mut memory = Vec::<usize>::new();
+        for batch in 0..5u32 {
+            mpt.insert(batch, batch);
+            memory.push(estimate_memory(mpt.get_root()));
+            log.seal_snapshot(mpt.clone());
+        }
+        assert_eq!(log.prune(&RetentionPolicy::NewerThan(0)).get_snapshots_removed(), 0);
+
+        //a snapshot still held by a reader is dropped by the RootLog, but not reclaimed yet
+        let held = log.get_snapshot(1).unwrap();
+        let report = log.prune(&RetentionPolicy::KeepLast(2));
+        assert_eq!(report.get_snapshots_removed(), 3);
+        assert_eq!(report.get_snapshots_in_use(), 1);
+        assert_eq!(report.get_bytes_reclaimed(), memory[0] + memory[2]);
+        assert_eq!(log.prove_at(0, &0), None);
+        assert!(log.prove_at(4, &0).is_some());
+        assert!(held.get(&1).is_some());
+
+        let report = log.prune(&RetentionPolicy::NewerThan(u64::MAX));
+        assert_eq!(report.get_bytes_reclaimed(), memory[3] + memory[4]);
+        assert_eq!(log.len(), 5);
+        assert_eq!(log.get_record(4).unwrap().get_leaf_count(), 5);
+    }
 }
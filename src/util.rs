@@ -1,7 +1,101 @@
+use serde::{Deserialize, Serialize};
+use talk::crypto::primitives::hash::{hash, Hash};
+
 /// Given an index representing the depth of a node in the Merkle Patricia Tree, returns true if the bit is 1,
 /// false if the bit is 0 (the array of u8 contains 256 total bits).
 pub fn get_bit_direction(arr: &[u8; 32], index: u8) -> bool {
-    let byte = arr[(index / 8) as usize];
-    let sub_index: u8 = 1 << (7 - (index % 8));
-    (byte & sub_index) > 0
+    get_digit(arr, index, 1) == 1
 }
+
+/// Given an index representing the depth of a node in a Merkle Patricia Tree walking the given
+/// number of bits per level (1, 2, 4 or 8), returns the digit formed by the bits of that level,
+/// which is the position of the child to follow (the array of u8 contains 256 total bits).
+pub fn get_digit(arr: &[u8; 32], index: u8, bits_per_level: u8) -> usize {
+    let first_bit = index as usize * bits_per_level as usize;
+    let byte = arr[first_bit / 8];
+    let shift = 8 - bits_per_level as usize - first_bit % 8;
+    let mask = ((1u16 << bits_per_level) - 1) as u8;
+    ((byte >> shift) & mask) as usize
+}
+
+/// The length of the longest prefix of bits a range can be asked for: no Leaf sits deeper.
+pub const MAX_PREFIX_LEN: usize = 255;
+
+/// Returns true if the first bits of the given array of u8 match the given prefix of bits,
+/// where true <--> 1 and false <--> 0. A prefix longer than the array never matches.
+pub fn starts_with_prefix(arr: &[u8; 32], prefix: &[bool]) -> bool {
+    prefix.len() <= 256
+        && prefix
+            .iter()
+            .enumerate()
+            .all(|(i, bit)| get_bit_direction(arr, i as u8) == *bit)
+}
+
+/// Returns the number of leading bits shared by the two given arrays of u8, 
+/// 256 if the arrays are equal.
+pub fn shared_bits(a: &[u8; 32], b: &[u8; 32]) -> usize {
+    match a.iter().zip(b.iter()).position(|(x, y)| x != y) {
+        Some(i) => i * 8 + (a[i] ^ b[i]).leading_zeros() as usize,
+        None => 256,
+    }
+}
+
+/// A key which can be placed in a MerkleTree. The path of a key is the array of 256 bits 
+/// followed from the root down to its Leaf (true <--> 1 <--> Right, false <--> 0 <--> Left).
+/// By default, the path of a key is its Hash. Keys which already are uniformly distributed, 
+/// such as Hashes, or which are to be placed in order, may provide their own path: two 
+/// different keys whose paths share the first 255 bits collide.
+/// A key looked up through a borrowed form Q, as K: Borrow<Q> allows, is only found if
+/// the path of the borrowed form is the one of the key: k.borrow().key_path() == k.key_path().
+/// Types without a KeyPath of their own, such as user structs, can be wrapped in a HashedKey.
+pub trait KeyPath: Serialize {
+    /// Returns the path of the key.
+    fn key_path(&self) -> [u8; 32] {
+        hash(&self).unwrap().to_bytes()
+    }
+}
+
+impl<T: KeyPath + ?Sized> KeyPath for &T {
+    fn key_path(&self) -> [u8; 32] {
+        (**self).key_path()
+    }
+}
+
+/// A Hash is used as its own path, rather than being hashed again.
+impl KeyPath for Hash {
+    fn key_path(&self) -> [u8; 32] {
+        self.to_bytes()
+    }
+}
+
+impl KeyPath for str {}
+impl KeyPath for String {}
+impl KeyPath for bool {}
+impl KeyPath for char {}
+impl KeyPath for u8 {}
+impl KeyPath for u16 {}
+impl KeyPath for u32 {}
+impl KeyPath for u64 {}
+impl KeyPath for u128 {}
+impl KeyPath for usize {}
+impl KeyPath for i8 {}
+impl KeyPath for i16 {}
+impl KeyPath for i32 {}
+impl KeyPath for i64 {}
+impl KeyPath for i128 {}
+impl KeyPath for isize {}
+impl<T: Serialize> KeyPath for Vec<T> {}
+impl<T: Serialize> KeyPath for [T] {}
+impl<T, const N: usize> KeyPath for [T; N] where [T; N]: Serialize {}
+impl<A: Serialize, B: Serialize> KeyPath for (A, B) {}
+impl<A: Serialize, B: Serialize, C: Serialize> KeyPath for (A, B, C) {}
+impl<A: Serialize, B: Serialize, C: Serialize, D: Serialize> KeyPath for (A, B, C, D) {}
+
+/// A key of any serializable type, whose path is the Hash of the wrapped value.
+/// The wrapper is transparent to serialization, so that the path and the Hash of the
+/// Leaf are the same as the ones of the wrapped value.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, Debug, Clone, Hash)]
+#[serde(transparent)]
+pub struct HashedKey<T>(pub T);
+
+impl<T: Serialize> KeyPath for HashedKey<T> {}